@@ -0,0 +1,183 @@
+//! Compares the two ways of finding the balls to merge, over a pile of 256 falling balls
+//!
+//! Both sides do the same work: list every pair of touching balls of the same size, without
+//! merging them. The pairwise scan checks the distance between every pair of balls, like merging
+//! did before, while `merge_check` now reads the pairs from the contacts of the physics engine.
+//! Each side is timed alone, and then as part of whole frames with physics.
+//!
+//! Run with `cargo bench --bench merge`
+
+use ball_blitz::balls::{Ball, BallCatalog, BallSize, BallTemplates, ExampleBall};
+use ball_blitz::BallBlitzConfig;
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_xpbd_3d::prelude::*;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Frames of the pile falling and settling
+const FRAMES: u32 = 300;
+/// Times each detection runs per frame
+const RUNS: u32 = 20;
+/// Distance between the surfaces of two balls that still counts as touching for the scan, since
+/// the solver pushes touching balls slightly apart
+const TOLERANCE: f32 = 0.01;
+
+type ScanParams<'w, 's> = (
+    Query<'w, 's, (Entity, &'static BallSize, &'static Transform), Without<ExampleBall>>,
+    Res<'w, BallTemplates>,
+);
+
+type ContactParams<'w, 's> = (
+    Query<'w, 's, (Entity, &'static BallSize), Without<ExampleBall>>,
+    Res<'w, Collisions>,
+);
+
+/// Touching pairs of balls of the same size, from the distance between every pair of balls
+fn pairwise_scan((query, templates): &ScanParams) -> Vec<(Entity, Entity)> {
+    query
+        .iter_combinations()
+        .filter(|[(_, size1, trans1), (_, size2, trans2)]| {
+            let reach = templates.radius(size1.0) * 2.0 + TOLERANCE;
+            size1.0 == size2.0
+                && trans1.translation.distance_squared(trans2.translation) <= reach * reach
+        })
+        .map(|[(ent1, ..), (ent2, ..)]| (ent1, ent2))
+        .collect()
+}
+
+/// Touching pairs of balls of the same size, from the contacts of the physics engine
+fn contact_pairs((query, collisions): &ContactParams) -> Vec<(Entity, Entity)> {
+    let sizes: HashMap<Entity, u8> = query.iter().map(|(ent, size)| (ent, size.0)).collect();
+    collisions
+        .iter()
+        .filter(|contacts| contacts.during_current_frame)
+        .filter(|contacts| {
+            matches!(
+                (sizes.get(&contacts.entity1), sizes.get(&contacts.entity2)),
+                (Some(size1), Some(size2)) if size1 == size2
+            )
+        })
+        .map(|contacts| (contacts.entity1, contacts.entity2))
+        .collect()
+}
+
+/// Physics without a window, with a box holding 256 stacked balls in alternating layers of ping
+/// pong and golf balls, where the golf balls of a layer touch
+fn pile_app() -> App {
+    let catalog = BallCatalog::read("assets/balls.catalog.ron").unwrap();
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        HierarchyPlugin,
+        PhysicsPlugins::default(),
+    ))
+    .insert_resource(Time::new_with(Physics::fixed_once_hz(60.0)))
+    .insert_resource(Gravity(BallBlitzConfig::default().gravity))
+    .insert_resource(BallTemplates::without_meshes(catalog));
+    // Box with a floor and four walls
+    for (size, translation) in [
+        (Vec3::new(8.0, 1.0, 8.0), Vec3::new(0.0, -0.5, 0.0)),
+        (Vec3::new(1.0, 80.0, 8.0), Vec3::new(-4.5, 40.0, 0.0)),
+        (Vec3::new(1.0, 80.0, 8.0), Vec3::new(4.5, 40.0, 0.0)),
+        (Vec3::new(8.0, 80.0, 1.0), Vec3::new(0.0, 40.0, -4.5)),
+        (Vec3::new(8.0, 80.0, 1.0), Vec3::new(0.0, 40.0, 4.5)),
+    ] {
+        app.world.spawn((
+            RigidBody::Static,
+            Collider::cuboid(size.x, size.y, size.z),
+            TransformBundle::from_transform(Transform::from_translation(translation)),
+        ));
+    }
+    for layer in 0..16 {
+        for x in 0..4 {
+            for z in 0..4 {
+                let size = 1 + (layer % 2) as u8;
+                let mut ball = Ball::new(size, app.world.resource::<BallTemplates>());
+                ball.spatial.transform.translation = Vec3::new(
+                    x as f32 * 2.0 - 3.0,
+                    layer as f32 * 2.0 + 1.0,
+                    z as f32 * 2.0 - 3.0,
+                );
+                app.world.spawn(ball);
+            }
+        }
+    }
+    app
+}
+
+/// Run a detection as a system after the physics step
+fn scan_frame(params: ScanParams) {
+    black_box(pairwise_scan(&params));
+}
+
+fn contact_frame(params: ContactParams) {
+    black_box(contact_pairs(&params));
+}
+
+/// Time whole frames of the pile with a detection system, returning the average and slowest
+/// frame
+fn time_frames<M>(detect: impl IntoSystemConfigs<M>) -> (Duration, Duration) {
+    let mut app = pile_app();
+    app.add_systems(PostUpdate, detect.after(PhysicsSet::Sync));
+    let mut total = Duration::ZERO;
+    let mut slowest = Duration::ZERO;
+    for _ in 0..FRAMES {
+        let start = Instant::now();
+        app.update();
+        let frame = start.elapsed();
+        total += frame;
+        slowest = slowest.max(frame);
+    }
+    (total / FRAMES, slowest)
+}
+
+/// Time one detection, returning its total time and the pairs it found
+fn time_runs(detect: impl Fn() -> Vec<(Entity, Entity)>) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut pairs = 0;
+    for _ in 0..RUNS {
+        pairs = black_box(detect()).len();
+    }
+    (start.elapsed(), pairs)
+}
+
+fn main() {
+    let mut app = pile_app();
+    let mut scan_state = SystemState::<ScanParams>::new(&mut app.world);
+    let mut contact_state = SystemState::<ContactParams>::new(&mut app.world);
+    let (mut scan_time, mut scan_pairs) = (Duration::ZERO, 0);
+    let (mut contact_time, mut contact_pairs_found) = (Duration::ZERO, 0);
+    for _ in 0..FRAMES {
+        app.update();
+        let params = scan_state.get(&app.world);
+        let (time, pairs) = time_runs(|| pairwise_scan(&params));
+        scan_time += time;
+        scan_pairs += pairs;
+        let params = contact_state.get(&app.world);
+        let (time, pairs) = time_runs(|| contact_pairs(&params));
+        contact_time += time;
+        contact_pairs_found += pairs;
+    }
+    let runs = FRAMES * RUNS;
+    let balls = app
+        .world
+        .query_filtered::<(), With<BallSize>>()
+        .iter(&app.world)
+        .count();
+    println!("{balls} balls, {FRAMES} frames, {RUNS} runs per frame");
+    println!(
+        "pairwise scan: {:?} per run, {scan_pairs} touching pairs over all frames",
+        scan_time / runs
+    );
+    println!(
+        "contacts:      {:?} per run, {contact_pairs_found} touching pairs over all frames",
+        contact_time / runs
+    );
+    let (average, slowest) = time_frames(scan_frame);
+    println!("pairwise scan frames: {average:?} on average, {slowest:?} at most");
+    let (average, slowest) = time_frames(contact_frame);
+    println!("contacts frames:      {average:?} on average, {slowest:?} at most");
+}
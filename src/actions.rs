@@ -0,0 +1,518 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::settings::Settings;
+use crate::widgets;
+
+/// Something the player does, triggered by any of its bindings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Drop the ball, by releasing a pointer over the box or pressing a key or button
+    Drop,
+    /// Orbit the camera, by dragging a pointer or holding a key or button while aiming
+    Orbit,
+    /// Zoom the camera, by dragging a pointer or holding a key or button while aiming
+    Zoom,
+    /// Move the drop point away from the camera
+    AimUp,
+    /// Move the drop point towards the camera
+    AimDown,
+    /// Move the drop point to the left of the screen
+    AimLeft,
+    /// Move the drop point to the right of the screen
+    AimRight,
+    /// Orbit the camera to the left while held
+    OrbitLeft,
+    /// Orbit the camera to the right while held
+    OrbitRight,
+    /// Swap the current ball with the held ball
+    Hold,
+    /// Pause and resume the round
+    Pause,
+    /// Start a new game
+    Restart,
+    /// End the round from the pause screen
+    EndRound,
+    /// Drop a ball of size 5 instead of the next one, for testing
+    Debug,
+    /// Turn the camera to look at the box from the side
+    FrontView,
+    /// Turn the camera to look down into the box
+    TopView,
+    /// Turn the camera to look down at a corner of the box
+    IsometricView,
+    /// Mute and unmute the sound
+    Mute,
+    /// Open the settings from the title or pause screen
+    OpenSettings,
+    /// Clear the high scores from the title or end screen, asking for confirmation first
+    ClearHighScores,
+}
+
+impl Action {
+    /// All actions in the order they are listed in the settings
+    pub const ALL: [Action; 20] = [
+        Action::Drop,
+        Action::Orbit,
+        Action::Zoom,
+        Action::AimUp,
+        Action::AimDown,
+        Action::AimLeft,
+        Action::AimRight,
+        Action::OrbitLeft,
+        Action::OrbitRight,
+        Action::Hold,
+        Action::Pause,
+        Action::Restart,
+        Action::EndRound,
+        Action::Debug,
+        Action::FrontView,
+        Action::TopView,
+        Action::IsometricView,
+        Action::Mute,
+        Action::OpenSettings,
+        Action::ClearHighScores,
+    ];
+    /// Bindings of the action when the player has not changed them
+    pub fn default_bindings(&self) -> Vec<Binding> {
+        match self {
+            Action::Drop => vec![
+                Binding::Mouse(MouseButton::Left),
+                Binding::Touch,
+                Binding::Key(KeyCode::Space),
+                Binding::Gamepad(GamepadButtonType::South),
+            ],
+            Action::Orbit => vec![
+                Binding::Mouse(MouseButton::Left),
+                Binding::Mouse(MouseButton::Right),
+                Binding::Touch,
+            ],
+            Action::Zoom => vec![
+                Binding::Mouse(MouseButton::Middle),
+                Binding::Gamepad(GamepadButtonType::LeftTrigger),
+            ],
+            Action::AimUp => vec![Binding::Key(KeyCode::Up), Binding::Key(KeyCode::W)],
+            Action::AimDown => vec![Binding::Key(KeyCode::Down), Binding::Key(KeyCode::S)],
+            Action::AimLeft => vec![Binding::Key(KeyCode::Left), Binding::Key(KeyCode::A)],
+            Action::AimRight => vec![Binding::Key(KeyCode::Right), Binding::Key(KeyCode::D)],
+            Action::OrbitLeft => vec![Binding::Key(KeyCode::Q)],
+            Action::OrbitRight => vec![Binding::Key(KeyCode::E)],
+            Action::Hold => vec![
+                Binding::Key(KeyCode::H),
+                Binding::Gamepad(GamepadButtonType::West),
+            ],
+            Action::Pause => vec![
+                Binding::Key(KeyCode::Escape),
+                Binding::Gamepad(GamepadButtonType::Start),
+            ],
+            Action::Restart => vec![
+                Binding::Key(KeyCode::N),
+                Binding::Gamepad(GamepadButtonType::Select),
+            ],
+            Action::EndRound => vec![
+                Binding::Key(KeyCode::End),
+                Binding::Gamepad(GamepadButtonType::North),
+            ],
+            // The cheat is only bound in debug builds
+            Action::Debug if cfg!(debug_assertions) => vec![Binding::Key(KeyCode::ShiftLeft)],
+            Action::Debug => vec![],
+            Action::FrontView => vec![
+                Binding::Key(KeyCode::Key1),
+                Binding::Gamepad(GamepadButtonType::DPadDown),
+            ],
+            Action::TopView => vec![
+                Binding::Key(KeyCode::Key2),
+                Binding::Gamepad(GamepadButtonType::DPadUp),
+            ],
+            Action::IsometricView => vec![
+                Binding::Key(KeyCode::Key3),
+                Binding::Gamepad(GamepadButtonType::DPadRight),
+            ],
+            Action::Mute => vec![Binding::Key(KeyCode::M)],
+            Action::OpenSettings => vec![Binding::Key(KeyCode::O)],
+            Action::ClearHighScores => vec![Binding::Key(KeyCode::C)],
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::AimUp => write!(f, "Aim up"),
+            Action::AimDown => write!(f, "Aim down"),
+            Action::AimLeft => write!(f, "Aim left"),
+            Action::AimRight => write!(f, "Aim right"),
+            Action::OrbitLeft => write!(f, "Orbit left"),
+            Action::OrbitRight => write!(f, "Orbit right"),
+            Action::EndRound => write!(f, "End round"),
+            Action::FrontView => write!(f, "Front view"),
+            Action::TopView => write!(f, "Top view"),
+            Action::IsometricView => write!(f, "Isometric view"),
+            Action::OpenSettings => write!(f, "Open settings"),
+            Action::ClearHighScores => write!(f, "Clear high scores"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// A key, mouse button, touch or gamepad button triggering an action
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// A finger on the screen, only used by pointer actions
+    Touch,
+    /// A button of any connected gamepad
+    Gamepad(GamepadButtonType),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+            Binding::Mouse(MouseButton::Other(button)) => write!(f, "Mouse {}", button),
+            Binding::Mouse(button) => write!(f, "{:?} click", button),
+            Binding::Touch => write!(f, "Touch"),
+            Binding::Gamepad(button) => write!(f, "Pad {:?}", button),
+        }
+    }
+}
+
+/// Bindings of every action
+/// Actions missing from the map use their default bindings, so actions added later are bound in
+/// older settings files
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ActionMap {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl ActionMap {
+    /// Bindings of an action
+    pub fn bindings(&self, action: Action) -> Vec<Binding> {
+        self.bindings
+            .get(&action)
+            .cloned()
+            .unwrap_or_else(|| action.default_bindings())
+    }
+    /// True if the binding triggers the action
+    pub fn is_bound(&self, action: Action, binding: Binding) -> bool {
+        self.bindings(action).contains(&binding)
+    }
+    /// Bind the action to the binding, or unbind it if it was already bound
+    pub fn toggle(&mut self, action: Action, binding: Binding) {
+        let mut bindings = self.bindings(action);
+        if let Some(index) = bindings.iter().position(|bound| *bound == binding) {
+            bindings.remove(index);
+        } else {
+            bindings.push(binding);
+        }
+        self.bindings.insert(action, bindings);
+    }
+    /// Remove all bindings of the action
+    pub fn clear(&mut self, action: Action) {
+        self.bindings.insert(action, Vec::new());
+    }
+    /// Bindings of the action for the prompts of the screens, like "H or Pad West", or None if
+    /// the action is unbound
+    pub fn prompt(&self, action: Action) -> Option<String> {
+        let names: Vec<String> = self
+            .bindings(action)
+            .iter()
+            .map(|binding| binding.to_string())
+            .collect();
+        (!names.is_empty()).then(|| names.join(" or "))
+    }
+    /// Mouse buttons bound to the action
+    pub fn mouse_buttons(&self, action: Action) -> impl Iterator<Item = MouseButton> {
+        self.bindings(action)
+            .into_iter()
+            .filter_map(|binding| match binding {
+                Binding::Mouse(button) => Some(button),
+                _ => None,
+            })
+    }
+}
+
+/// Current state of the actions from the keyboard, mouse and gamepads
+/// No action triggers while the settings screen waits for a binding, so the key being bound does
+/// nothing else
+#[derive(SystemParam)]
+pub struct ActionInput<'w> {
+    settings: Res<'w, Settings>,
+    rebinding: Res<'w, Rebinding>,
+    keys: Res<'w, Input<KeyCode>>,
+    mouse_buttons: Res<'w, Input<MouseButton>>,
+    gamepads: Res<'w, Gamepads>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+}
+
+impl<'w> ActionInput<'w> {
+    pub fn map(&self) -> &ActionMap {
+        &self.settings.controls
+    }
+    /// True if any binding of the action matches, touches never do
+    fn any(
+        &self,
+        action: Action,
+        keys: impl Fn(KeyCode) -> bool,
+        mouse_buttons: impl Fn(MouseButton) -> bool,
+        gamepad_buttons: impl Fn(GamepadButton) -> bool,
+    ) -> bool {
+        if self.rebinding.0.is_some() {
+            return false;
+        }
+        self.map()
+            .bindings(action)
+            .into_iter()
+            .any(|binding| match binding {
+                Binding::Key(key) => keys(key),
+                Binding::Mouse(button) => mouse_buttons(button),
+                Binding::Touch => false,
+                Binding::Gamepad(button_type) => self
+                    .gamepads
+                    .iter()
+                    .any(|gamepad| gamepad_buttons(GamepadButton::new(gamepad, button_type))),
+            })
+    }
+    /// True if a key or button of the action was pressed this frame
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.any(
+            action,
+            |key| self.keys.just_pressed(key),
+            |button| self.mouse_buttons.just_pressed(button),
+            |button| self.gamepad_buttons.just_pressed(button),
+        )
+    }
+    /// True if a key or button of the action is held down
+    pub fn pressed(&self, action: Action) -> bool {
+        self.any(
+            action,
+            |key| self.keys.pressed(key),
+            |button| self.mouse_buttons.pressed(button),
+            |button| self.gamepad_buttons.pressed(button),
+        )
+    }
+    /// True if a key or gamepad button of the action was pressed this frame, ignoring the mouse
+    /// Used by actions that the mouse triggers through pointer gestures instead
+    pub fn key_just_pressed(&self, action: Action) -> bool {
+        self.any(
+            action,
+            |key| self.keys.just_pressed(key),
+            |_| false,
+            |button| self.gamepad_buttons.just_pressed(button),
+        )
+    }
+    /// True if a key or gamepad button of the action is held down, ignoring the mouse
+    pub fn key_pressed(&self, action: Action) -> bool {
+        self.any(
+            action,
+            |key| self.keys.pressed(key),
+            |_| false,
+            |button| self.gamepad_buttons.pressed(button),
+        )
+    }
+    /// 1 if the positive action is held, -1 if the negative one is, and 0 for both or neither
+    pub fn key_axis(&self, negative: Action, positive: Action) -> f32 {
+        self.key_pressed(positive) as i32 as f32 - self.key_pressed(negative) as i32 as f32
+    }
+}
+
+/// Action of the settings screen waiting for a key or button to bind
+#[derive(Resource, Default)]
+pub struct Rebinding(pub Option<Action>);
+
+/// Button of the settings screen rebinding an action
+#[derive(Component)]
+pub struct ControlButton(Action);
+
+/// Label struct for the button restoring the default bindings
+#[derive(Component)]
+pub struct ResetControlsButton;
+
+/// Text of a control button, listing the bindings or asking for a new one
+fn control_label(action: Action, map: &ActionMap, rebinding: &Rebinding) -> String {
+    if rebinding.0 == Some(action) {
+        return format!("{}: press to bind or unbind (Backspace clears)", action);
+    }
+    let bindings = map.bindings(action);
+    if bindings.is_empty() {
+        return format!("{}: unbound", action);
+    }
+    let names: Vec<String> = bindings.iter().map(|binding| binding.to_string()).collect();
+    format!("{}: {}", action, names.join(", "))
+}
+
+/// Spawn a button for each action and a button to reset them
+pub fn spawn_controls(parent: &mut ChildBuilder, style: &TextStyle, map: &ActionMap) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_wrap: FlexWrap::Wrap,
+                justify_content: JustifyContent::Center,
+                max_width: Val::Px(900.0),
+                column_gap: Val::Px(10.0),
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|buttons| {
+            for action in Action::ALL {
+                let label = control_label(action, map, &Rebinding::default());
+                widgets::spawn_button(buttons, style, &label, ControlButton(action));
+            }
+            widgets::spawn_button(buttons, style, "Reset controls", ResetControlsButton);
+        });
+}
+
+/// Start rebinding an action when its button is clicked, then toggle the next key, mouse button,
+/// touch or gamepad button on it
+/// Escape cancels and Backspace removes all bindings of the action
+#[allow(clippy::too_many_arguments)]
+pub fn rebind_input(
+    buttons: Query<(Ref<Interaction>, &ControlButton)>,
+    reset: Query<&Interaction, (With<ResetControlsButton>, Changed<Interaction>)>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    touches: Res<Touches>,
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Settings>,
+) {
+    // The click on a button is not a binding
+    for (interaction, button) in buttons.iter() {
+        if interaction.is_changed() && *interaction == Interaction::Pressed {
+            rebinding.0 = Some(button.0);
+            return;
+        }
+    }
+    if reset
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        settings.controls = ActionMap::default();
+        rebinding.0 = None;
+        return;
+    }
+    let Some(action) = rebinding.0 else {
+        return;
+    };
+    if keys.just_pressed(KeyCode::Escape) {
+        rebinding.0 = None;
+        return;
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        settings.controls.clear(action);
+        rebinding.0 = None;
+        return;
+    }
+    let binding = keys
+        .get_just_pressed()
+        .next()
+        .map(|key| Binding::Key(*key))
+        .or_else(|| {
+            mouse_buttons
+                .get_just_pressed()
+                .next()
+                .map(|button| Binding::Mouse(*button))
+        })
+        .or_else(|| {
+            gamepad_buttons
+                .get_just_pressed()
+                .next()
+                .map(|button| Binding::Gamepad(button.button_type))
+        })
+        .or_else(|| touches.any_just_pressed().then_some(Binding::Touch));
+    if let Some(binding) = binding {
+        settings.controls.toggle(action, binding);
+        rebinding.0 = None;
+    }
+}
+
+/// Keep the labels of the control buttons up to date
+pub fn update_control_labels(
+    buttons: Query<(&ControlButton, &Children)>,
+    mut texts: Query<&mut Text>,
+    settings: Res<Settings>,
+    rebinding: Res<Rebinding>,
+) {
+    for (button, children) in buttons.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.sections[0].value = control_label(button.0, &settings.controls, &rebinding);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn toggling_adds_and_removes_bindings() {
+        let mut map = ActionMap::default();
+        assert!(map.is_bound(Action::Hold, Binding::Key(KeyCode::H)));
+        map.toggle(Action::Hold, Binding::Key(KeyCode::H));
+        assert!(!map.is_bound(Action::Hold, Binding::Key(KeyCode::H)));
+        map.toggle(Action::Hold, Binding::Mouse(MouseButton::Right));
+        assert!(map.is_bound(Action::Hold, Binding::Mouse(MouseButton::Right)));
+        map.clear(Action::Hold);
+        assert!(map.bindings(Action::Hold).is_empty());
+    }
+
+    #[test]
+    fn missing_actions_use_default_bindings() {
+        let map: ActionMap = ron::from_str("{Hold: [Key(J)]}").unwrap();
+        assert_eq!(map.bindings(Action::Hold), vec![Binding::Key(KeyCode::J)]);
+        assert_eq!(
+            map.bindings(Action::Pause),
+            Action::Pause.default_bindings()
+        );
+    }
+
+    #[test]
+    fn prompts_name_every_binding() {
+        let mut map = ActionMap::default();
+        assert_eq!(map.prompt(Action::Hold).unwrap(), "H or Pad West");
+        map.clear(Action::Hold);
+        assert_eq!(map.prompt(Action::Hold), None);
+    }
+
+    #[test]
+    fn actions_wait_for_a_pending_rebinding() {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::M);
+        world.insert_resource(keys);
+        world.init_resource::<Input<MouseButton>>();
+        world.init_resource::<Gamepads>();
+        world.init_resource::<Input<GamepadButton>>();
+        world.init_resource::<Settings>();
+        world.insert_resource(Rebinding(Some(Action::Hold)));
+        let muted = |actions: ActionInput| actions.just_pressed(Action::Mute);
+        assert!(!world.run_system_once(muted));
+        world.resource_mut::<Rebinding>().0 = None;
+        assert!(world.run_system_once(muted));
+    }
+
+    #[test]
+    fn default_keys_and_buttons_trigger_one_action() {
+        let bindings: Vec<Binding> = Action::ALL
+            .iter()
+            .flat_map(|action| action.default_bindings())
+            .filter(|binding| matches!(binding, Binding::Key(_) | Binding::Gamepad(_)))
+            .collect();
+        for (i, binding) in bindings.iter().enumerate() {
+            assert!(
+                !bindings[i + 1..].contains(binding),
+                "{binding} is bound twice"
+            );
+        }
+    }
+}
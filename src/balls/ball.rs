@@ -1,3 +1,4 @@
+use super::catalog::{BallCatalog, BallCatalogHandle, BallTier, BoxGrowth};
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::*;
@@ -10,146 +11,166 @@ pub struct BallSize(pub u8);
 #[derive(Component)]
 pub struct ExampleBall(pub ());
 
-/// Stores each ball template as a list of PbrBundles
+/// Order in which balls were spawned, lower is older
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpawnOrder(pub u64);
+
+/// Hands out spawn orders to new balls
+#[derive(Resource, Default)]
+pub struct SpawnCounter(u64);
+
+impl SpawnCounter {
+    /// Return the order of the next spawned ball
+    pub fn next_order(&mut self) -> SpawnOrder {
+        self.0 += 1;
+        SpawnOrder(self.0)
+    }
+}
+
+/// Give a spawn order to every ball that doesn't have one yet
+/// Balls spawned during the same frame are numbered by entity
+pub fn number_balls(
+    mut commands: Commands,
+    query: Query<Entity, (With<BallSize>, Without<SpawnOrder>)>,
+    mut counter: ResMut<SpawnCounter>,
+) {
+    let mut new_balls: Vec<Entity> = query.iter().collect();
+    new_balls.sort();
+    for ent in new_balls {
+        commands.entity(ent).insert(counter.next_order());
+    }
+}
+
+/// Stores the catalog tiers and each ball template as a list of PbrBundles
 #[derive(Resource)]
 pub struct BallTemplates {
+    tiers: Vec<BallTier>,
     meshes: Vec<Vec<PbrBundle>>,
+    final_tier: u8,
+    final_bonus: i32,
+    box_growth: Option<BoxGrowth>,
 }
 
-/// Creates an instance of BallTemplates and inserts it as a resource
-pub fn load_ball_templates(
+/// Creates an instance of BallTemplates from the catalog and inserts it as a resource
+/// Runs again whenever the catalog file changes
+pub fn build_ball_templates(
     mut commands: Commands,
+    mut events: EventReader<AssetEvent<BallCatalog>>,
+    catalogs: Res<Assets<BallCatalog>>,
+    handle: Option<Res<BallCatalogHandle>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     assets: Res<AssetServer>,
 ) {
-    // Default colors for balls
-    let colors: Vec<Color> = vec![
-        Color::rgb(1.0, 0.5, 1.0),       // Zero ball (not used)
-        Color::rgb(1.0, 0.45, 0.3),      // Ping pong
-        Color::rgb(0.9, 0.9, 0.9),       // Golf
-        Color::rgb(0.0, 0.0, 0.4),       // Pool
-        Color::rgb(0.369, 0.624, 0.0),   // Tennis
-        Color::rgb(1.0, 1.0, 1.0),       // Baseball
-        Color::rgb(0.8, 0.8, 0.8),       // Soccer
-        Color::rgb(0.612, 0.145, 0.036), // Basketball
-        Color::rgb(1.0, 1.0, 1.0),       // Beach ball
-        Color::rgb(1.0, 0.0, 1.0),
-    ];
-    // List of models for different ball sizes
-    // Each has file name (in assets directory), scale, and list of primitives with their material
-    let models: Vec<Option<(&'static str, f32, Vec<(&'static str, &'static str)>)>> = vec![
-        None,
-        None,
-        Some(("Golf.glb", 0.31, vec![("Mesh0/Primitive0", "Material0")])),
-        Some((
-            "Pool.glb",
-            0.95,
-            vec![
-                ("Mesh0/Primitive0", "Material0"),
-                ("Mesh0/Primitive1", "Material1"),
-                ("Mesh1/Primitive0", "Material2"),
-                ("Mesh2/Primitive0", "Material3"),
-            ],
-        )),
-        Some((
-            "Tennis.glb",
-            0.0106,
-            vec![
-                ("Mesh0/Primitive0", "Material0"),
-                ("Mesh1/Primitive0", "Material1"),
-            ],
-        )),
-        Some((
-            "Baseball.glb",
-            0.4,
-            vec![
-                ("Mesh0/Primitive0", "Material0"),
-                ("Mesh0/Primitive1", "Material1"),
-                ("Mesh0/Primitive2", "Material2"),
-            ],
-        )),
-        Some(("Bowling.glb", 6.85, vec![("Mesh0/Primitive0", "Material0")])),
-        Some((
-            "Soccer.glb",
-            2.16,
-            vec![
-                ("Mesh0/Primitive0", "Material0"),
-                ("Mesh0/Primitive1", "Material1"),
-            ],
-        )),
-        Some((
-            "Basketball.glb",
-            4.7,
-            vec![
-                ("Mesh0/Primitive0", "Material0"),
-                ("Mesh1/Primitive0", "Material1"),
-            ],
-        )),
-        Some((
-            "Beach_ball.glb",
-            2.65,
-            vec![
-                ("Mesh0/Primitive0", "Material0"),
-                ("Mesh1/Primitive0", "Material1"),
-                ("Mesh2/Primitive0", "Material2"),
-                ("Mesh3/Primitive0", "Material3"),
-            ],
-        )),
-        None,
-        None,
-        None,
-        None,
-    ];
-    let pbr_bundles: Vec<Vec<PbrBundle>> = (0..10u8)
-        .map(|idx| match &models[idx as usize] {
-            // If no texture provided, spawn a sphere based on colors
+    let Some(handle) = handle else {
+        return;
+    };
+    let changed = events.read().any(|event| match event {
+        AssetEvent::Added { id } | AssetEvent::Modified { id } => *id == handle.0.id(),
+        _ => false,
+    });
+    let Some(catalog) = catalogs.get(&handle.0).filter(|_| changed) else {
+        return;
+    };
+    let pbr_bundles: Vec<Vec<PbrBundle>> = catalog
+        .tiers
+        .iter()
+        .map(|tier| match &tier.model {
+            // If no model provided, spawn a sphere based on the fallback color
             None => vec![PbrBundle {
                 material: materials.add(StandardMaterial {
-                    base_color: colors[idx as usize],
+                    base_color: Color::rgb(tier.color.0, tier.color.1, tier.color.2),
                     perceptual_roughness: 0.67,
                     specular_transmission: 0.5,
                     ..default()
                 }),
-                mesh: meshes.add(Mesh::from(shape::UVSphere {
-                    radius: BallSize(idx).start_radius(),
-                    sectors: (BallSize(idx).radius() * 10. + 20.) as usize,
-                    stacks: (BallSize(idx).radius() * 10. + 20.) as usize,
-                })),
+                mesh: meshes.add(sphere_mesh(tier.radius, 1.0)),
                 ..default()
             }],
-            // If texture is provided, load each mesh using a handle
-            Some((file, scale, meshes)) => meshes
+            // If model is provided, load each mesh using a handle
+            Some(model) => model
+                .primitives
                 .iter()
                 .map(|(mesh, mat)| PbrBundle {
-                    material: assets.load((*file).to_owned() + "#" + *mat),
-                    mesh: assets.load((*file).to_owned() + "#" + *mesh),
-                    transform: Transform {
-                        scale: Vec3::from_array([*scale; 3]),
-                        ..default()
-                    },
+                    material: assets.load(format!("{}#{}", model.path, mat)),
+                    mesh: assets.load(format!("{}#{}", model.path, mesh)),
+                    transform: Transform::from_scale(Vec3::from_array([model.scale; 3])),
                     ..default()
                 })
                 .collect(),
         })
         .collect();
     commands.insert_resource(BallTemplates {
+        tiers: catalog.tiers.clone(),
         meshes: pbr_bundles,
+        final_tier: catalog.final_tier,
+        final_bonus: catalog.final_bonus,
+        box_growth: catalog.box_growth,
     });
 }
 
-impl BallSize {
-    /// Return the floating point radius of a ball bundle
-    pub fn radius(&self) -> f32 {
-        self.0 as f32 * 0.3 + 0.4
+/// Sphere of a ball without a model, with more faces for larger balls and a higher detail
+fn sphere_mesh(radius: f32, detail: f32) -> Mesh {
+    let segments = ((radius * 10. + 20.) * detail).max(8.) as usize;
+    Mesh::from(shape::UVSphere {
+        radius,
+        sectors: segments,
+        stacks: segments,
+    })
+}
+
+impl BallTemplates {
+    /// Rebuild the spheres of the tiers without a model with a level of detail
+    /// Balls already in the box share the sphere meshes, so they change too
+    pub fn set_sphere_detail(&self, detail: f32, meshes: &mut Assets<Mesh>) {
+        for (tier, bundles) in self.tiers.iter().zip(self.meshes.iter()) {
+            if let (None, Some(bundle)) = (&tier.model, bundles.first()) {
+                meshes.insert(bundle.mesh.clone(), sphere_mesh(tier.radius, detail));
+            }
+        }
+    }
+    /// Index of the tier of a ball size, clamped to the catalog so a size that is not in it
+    /// can't panic
+    fn index(&self, size: u8) -> usize {
+        (size as usize).clamp(1, self.tiers.len()) - 1
+    }
+    /// Return the catalog tier of a ball size, or its closest tier for sizes outside the catalog
+    pub fn tier(&self, size: u8) -> &BallTier {
+        &self.tiers[self.index(size)]
     }
-    /// Return the radius of the smaller starting ball
-    pub fn start_radius(&self) -> f32 {
-        (self.0 as f32 * 0.3 - 0.3 + 0.4) * 0.95
+    /// Return the radius of a fully grown ball
+    pub fn radius(&self, size: u8) -> f32 {
+        self.tier(size).radius
     }
-    /// Return the scale factor between the previous ball size and the current ball size
-    pub fn scale(&self) -> f32 {
-        self.radius() / self.start_radius()
+    /// Return the size of the largest ball
+    pub fn final_tier(&self) -> u8 {
+        self.final_tier
+    }
+    /// Return the points gained when two balls of the final tier vanish
+    pub fn final_bonus(&self) -> i32 {
+        self.final_bonus
+    }
+    /// Return the tier that makes the box larger and the size it grows to
+    pub fn box_growth(&self) -> Option<BoxGrowth> {
+        self.box_growth
+    }
+}
+
+impl BallTemplates {
+    /// Templates of a catalog without any meshes, for running the game without rendering
+    pub fn without_meshes(catalog: BallCatalog) -> Self {
+        Self {
+            meshes: vec![vec![]; catalog.tiers.len()],
+            tiers: catalog.tiers,
+            final_tier: catalog.final_tier,
+            final_bonus: catalog.final_bonus,
+            box_growth: catalog.box_growth,
+        }
+    }
+    /// Templates of the builtin catalog without any meshes
+    #[cfg(test)]
+    pub fn empty() -> Self {
+        Self::without_meshes(BallCatalog::builtin())
     }
 }
 
@@ -166,37 +187,24 @@ pub struct Ball {
     friction: Friction,
 }
 
-const DEFAULT_RESTITUTION: f32 = 0.0;
-const DEFAULT_FRICTION: f32 = 0.8;
-
-impl Default for Ball {
-    fn default() -> Self {
-        Self {
-            size: BallSize(1),
+impl Ball {
+    /// Create a new ball from specific size, with the properties of its catalog tier
+    pub fn new(size: u8, templates: &BallTemplates) -> Self {
+        let tier = templates.tier(size);
+        Ball {
+            size: BallSize(size),
             rigid_body: RigidBody::Dynamic,
-            collider: Collider::ball(BallSize(1).start_radius()),
-            restitution: Restitution::new(DEFAULT_RESTITUTION),
-            friction: Friction::new(DEFAULT_FRICTION),
-            mass: Mass(1.0),
-            vel: LinearVelocity(Vec3::new(0., 0., 0.)),
-            spatial: SpatialBundle { ..default() },
+            collider: Collider::ball(tier.radius),
+            vel: LinearVelocity(Vec3::ZERO),
+            spatial: SpatialBundle::default(),
+            mass: Mass(tier.mass),
+            restitution: Restitution::new(tier.restitution),
+            friction: Friction::new(tier.friction),
         }
     }
-}
-
-impl Ball {
-    /// Create a new ball from specific size
-    pub fn new(size: u8) -> Self {
-        let mut out: Ball = Ball::default();
-        out.size = BallSize(size);
-        out.collider = Collider::ball(BallSize(size).start_radius());
-        out.spatial.transform.scale = Vec3::from_array([BallSize(size).scale(); 3]);
-        out.mass = Mass((size as f32) * (size as f32) * 10.0);
-        out
-    }
     /// Spawn meshes for a specific ball size and return them as a vector of entities
     pub fn get_meshes(size: u8, templates: &BallTemplates, commands: &mut Commands) -> Vec<Entity> {
-        templates.meshes[if size > 9 { 9 } else { size } as usize]
+        templates.meshes[templates.index(size)]
             .iter()
             .map(|pbr_bundle| commands.spawn(pbr_bundle.clone()).id())
             .collect()
@@ -0,0 +1,174 @@
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+
+use super::insertion::DEBUG_DROP_SIZE;
+use super::queue::MAX_QUEUE_SIZE;
+
+/// Path of the ball catalog in the assets directory
+pub const CATALOG_PATH: &str = "balls.catalog.ron";
+
+/// Ladder of ball tiers loaded from a catalog file
+/// The first tier is BallSize(1), and two balls of a tier merge into a ball of the next tier
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct BallCatalog {
+    pub tiers: Vec<BallTier>,
+    /// Size of the largest ball
+    /// Creating a ball of the final tier wins the round, and two of them vanish when they touch
+    pub final_tier: u8,
+    /// Points gained when two balls of the final tier vanish
+    pub final_bonus: i32,
+    /// Tier whose first ball makes the box larger, if any
+    #[serde(default)]
+    pub box_growth: Option<BoxGrowth>,
+}
+
+/// Growth of the box when a ball of a tier is created
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct BoxGrowth {
+    pub tier: u8,
+    /// Half size of the box along each axis once grown
+    pub size: (f32, f32, f32),
+}
+
+/// Properties of every ball of one size
+#[derive(Deserialize, Clone, Debug)]
+pub struct BallTier {
+    pub name: String,
+    pub radius: f32,
+    pub mass: f32,
+    pub restitution: f32,
+    pub friction: f32,
+    pub model: Option<BallModel>,
+    /// Color of the sphere used when there is no model
+    pub color: (f32, f32, f32),
+    /// Points gained when two balls of this tier merge
+    pub points: i32,
+}
+
+/// glTF model of a ball
+#[derive(Deserialize, Clone, Debug)]
+pub struct BallModel {
+    /// File name in the assets directory
+    pub path: String,
+    /// Scale of the model for a ball of the tier radius
+    pub scale: f32,
+    /// List of primitives with their material
+    pub primitives: Vec<(String, String)>,
+}
+
+impl BallCatalog {
+    /// Parse a catalog file and check that it has every size the queue and the debug drop can
+    /// give, and that its final tier and box growth tier exist
+    pub fn parse(bytes: &[u8]) -> Result<Self, BallCatalogError> {
+        let catalog: BallCatalog = ron::de::from_bytes(bytes)?;
+        let needed = MAX_QUEUE_SIZE.max(DEBUG_DROP_SIZE) as usize;
+        if catalog.tiers.len() < needed {
+            return Err(BallCatalogError::TooFewTiers {
+                tiers: catalog.tiers.len(),
+                needed,
+            });
+        }
+        let exists = |tier: u8| tier != 0 && tier as usize <= catalog.tiers.len();
+        if !exists(catalog.final_tier) {
+            return Err(BallCatalogError::FinalTier {
+                final_tier: catalog.final_tier,
+                tiers: catalog.tiers.len(),
+            });
+        }
+        if let Some(growth) = catalog.box_growth.filter(|growth| !exists(growth.tier)) {
+            return Err(BallCatalogError::BoxGrowthTier {
+                tier: growth.tier,
+                tiers: catalog.tiers.len(),
+            });
+        }
+        Ok(catalog)
+    }
+    /// Read and parse a catalog file outside of the asset server
+    pub fn read(path: &str) -> Result<Self, BallCatalogError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+    /// Catalog from the assets directory, parsed at compile time
+    #[cfg(test)]
+    pub fn builtin() -> Self {
+        Self::parse(include_bytes!("../../assets/balls.catalog.ron")).unwrap()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BallCatalogError {
+    #[error("could not read ball catalog: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse ball catalog: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("the ball catalog has {tiers} tiers but at least {needed} are needed")]
+    TooFewTiers { tiers: usize, needed: usize },
+    #[error("final tier {final_tier} is not one of the {tiers} tiers of the ball catalog")]
+    FinalTier { final_tier: u8, tiers: usize },
+    #[error("box growth tier {tier} is not one of the {tiers} tiers of the ball catalog")]
+    BoxGrowthTier { tier: u8, tiers: usize },
+}
+
+/// Loads files ending in .catalog.ron as a BallCatalog
+#[derive(Default)]
+pub struct BallCatalogLoader;
+
+impl AssetLoader for BallCatalogLoader {
+    type Asset = BallCatalog;
+    type Settings = ();
+    type Error = BallCatalogError;
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<BallCatalog, BallCatalogError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            BallCatalog::parse(&bytes)
+        })
+    }
+    fn extensions(&self) -> &[&str] {
+        &["catalog.ron"]
+    }
+}
+
+/// Handle keeping the catalog loaded
+#[derive(Resource)]
+pub struct BallCatalogHandle(pub Handle<BallCatalog>);
+
+/// Start loading the ball catalog
+pub fn load_ball_catalog(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(BallCatalogHandle(assets.load(CATALOG_PATH)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Catalog text with a number of plain tiers
+    fn catalog_text(tiers: usize, final_tier: u8) -> String {
+        let tier = "(name: \"Ball\", radius: 1.0, mass: 1.0, restitution: 0.0, friction: 0.8, \
+                    model: None, color: (1.0, 1.0, 1.0), points: 1)";
+        format!(
+            "(tiers: [{}], final_tier: {}, final_bonus: 10)",
+            vec![tier; tiers].join(", "),
+            final_tier
+        )
+    }
+
+    #[test]
+    fn catalogs_need_every_size_the_game_can_give() {
+        assert!(BallCatalog::parse(catalog_text(5, 5).as_bytes()).is_ok());
+        assert!(matches!(
+            BallCatalog::parse(catalog_text(3, 3).as_bytes()),
+            Err(BallCatalogError::TooFewTiers { tiers: 3, .. })
+        ));
+        assert!(matches!(
+            BallCatalog::parse(catalog_text(5, 6).as_bytes()),
+            Err(BallCatalogError::FinalTier { .. })
+        ));
+    }
+}
@@ -1,16 +1,32 @@
+use crate::actions::{Action, ActionInput};
 use crate::balls::*;
 use crate::input::BallSpawnUpdate;
 use crate::input::CursorChangeType;
-use crate::points;
+use crate::rng::GameRng;
 use crate::setup::BoxSize;
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::{Collider, Mass, RigidBody};
-use rand::{self, Rng};
+use rand::{Rng, RngCore};
 
-/// Iterate over every pair of balls and check if they should be merged
+/// Size of the ball dropped by the debug action
+pub const DEBUG_DROP_SIZE: u8 = 5;
+
+/// Sent when a ball is dropped into the box
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BallDropped {
+    pub size: u8,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// Move the example ball to the cursor and drop a ball when the cursor is released
+/// A drop wins over the other updates of the frame, so a key drop isn't hidden by the cursor
+#[allow(clippy::too_many_arguments)]
 pub fn insertion_check(
     mut event: EventReader<BallSpawnUpdate>,
-    keys: Res<Input<KeyCode>>,
+    mut dropped: EventWriter<BallDropped>,
+    actions: ActionInput,
     ball_templates: Res<BallTemplates>,
     box_size: Res<BoxSize>,
     mut commands: Commands,
@@ -19,16 +35,21 @@ pub fn insertion_check(
         (&BallSize, &mut Transform, Entity, &mut Visibility),
         With<ExampleBall>,
     >,
-    mut points: ResMut<points::GamePoints>,
+    mut queue: ResMut<BallQueue>,
+    mut rng: ResMut<GameRng>,
 ) {
+    let updates: Vec<BallSpawnUpdate> = event.read().copied().collect();
     let Some(BallSpawnUpdate {
         cursor_type,
         position,
-    }) = event.read().next().cloned()
+    }) = updates
+        .iter()
+        .find(|update| update.cursor_type == CursorChangeType::DragEnd && update.position.is_some())
+        .or(updates.last())
+        .copied()
     else {
         return;
     };
-    event.clear();
 
     let mut example_ball = match example_ball.get_single_mut() {
         Ok(ex) => ex,
@@ -43,7 +64,7 @@ pub fn insertion_check(
     };
     *example_ball.3 = Visibility::Visible;
     let size = example_ball.0 .0;
-    let radius = BallSize(size).radius() + 0.05;
+    let radius = ball_templates.radius(size) + 0.05;
     position.x = position.x.clamp(-box_size.x + radius, box_size.x - radius);
     position.z = position.z.clamp(-box_size.z + radius, box_size.z - radius);
     // Draw example ball and line
@@ -55,38 +76,68 @@ pub fn insertion_check(
     example_ball.1.translation = position;
     //Check if mouse pressed
     if cursor_type == CursorChangeType::DragEnd {
-        // Add points
-        points.as_mut().0 += size as i32;
-        // Spawn ball
-        let mut new_ball = Ball::new(size);
-        new_ball.spatial.transform.translation = position;
-        new_ball.spatial.transform.rotation = example_ball.1.rotation;
-        new_ball.spawn(&ball_templates, &mut commands);
-        let new_size = if keys.pressed(KeyCode::ShiftLeft) {
-            5
+        drop_ball(
+            BallDropped {
+                size,
+                position,
+                rotation: example_ball.1.rotation,
+            },
+            &ball_templates,
+            &mut commands,
+            &mut dropped,
+        );
+        let new_size = if actions.pressed(Action::Debug) {
+            DEBUG_DROP_SIZE
         } else {
-            rand::thread_rng().gen_range(1..=4)
+            queue.pop_next(rng.as_mut())
         };
         // Replace example ball
         commands.entity(example_ball.2).despawn_recursive();
-        let mut new_example_ball = Ball::new(new_size);
-        new_example_ball.spatial.transform.translation = Vec3::new(0.0, 4000.0, 0.0);
-        new_example_ball.spatial.transform.rotation = random_quaternion();
-        new_example_ball
-            .spawn(&ball_templates, &mut commands)
-            .remove::<Collider>()
-            .remove::<RigidBody>()
-            .insert(ExampleBall(()))
-            .insert(Mass(1.0));
+        spawn_example_ball(new_size, rng.as_mut(), &ball_templates, &mut commands);
     }
 }
 
+/// Spawn a dropped ball
+/// Shared by the player and replays so both drop balls the same way
+pub fn drop_ball(
+    drop: BallDropped,
+    ball_templates: &BallTemplates,
+    commands: &mut Commands,
+    dropped: &mut EventWriter<BallDropped>,
+) {
+    let mut new_ball = Ball::new(drop.size, ball_templates);
+    new_ball.spatial.transform.translation = drop.position;
+    new_ball.spatial.transform.rotation = drop.rotation;
+    new_ball.spawn(ball_templates, commands);
+    dropped.send(drop);
+}
+
+/// Spawn the example ball that follows the cursor, out of sight until the cursor is over the box
+/// It has no collider so it doesn't push the balls in the box
+pub fn spawn_example_ball<'w, 's, 'a>(
+    size: u8,
+    rng: &mut dyn RngCore,
+    ball_templates: &BallTemplates,
+    commands: &'a mut Commands<'w, 's>,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut example_ball = Ball::new(size, ball_templates);
+    example_ball.spatial.transform.translation = Vec3::new(0.0, 4000.0, 0.0);
+    example_ball.spatial.transform.rotation = random_quaternion(rng);
+    let mut entity_commands = example_ball.spawn(ball_templates, commands);
+    entity_commands
+        .remove::<Collider>()
+        .remove::<RigidBody>()
+        .insert(ExampleBall(()))
+        .insert(Mass(1.0));
+    entity_commands
+}
+
 /// Generate a random quaternion
-fn random_quaternion() -> Quat {
+fn random_quaternion(rng: &mut dyn RngCore) -> Quat {
     use std::f32::consts::PI;
-    let u = rand::random::<f32>();
-    let v = rand::random::<f32>();
-    let w = rand::random::<f32>();
+    let u = rng.gen::<f32>();
+    let v = rng.gen::<f32>();
+    let w = rng.gen::<f32>();
     Quat::from_xyzw(
         (1. - u).sqrt() * (2. * PI * v).sin(),
         (1. - u).sqrt() * (2. * PI * v).cos(),
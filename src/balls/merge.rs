@@ -1,95 +1,473 @@
 use crate::balls::*;
-use crate::points;
 use crate::setup::BoxScaleEvent;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 use bevy_xpbd_3d::prelude::*;
 
-/// Iterate over each possible ball pair and check if they should be merged
+/// Sent when two balls merge into a ball of the next size
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BallMerged {
+    /// Size of the new ball
+    pub size: u8,
+    /// Point where the two balls touched
+    pub position: Vec3,
+}
+
+/// Sent when two balls of the final tier touch and vanish
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FinalBallsVanished {
+    /// Point where the two balls touched
+    pub position: Vec3,
+}
+
+/// A ball taking part in merge resolution, either already spawned or created by a merge this frame
+#[derive(Clone, Debug)]
+struct MergeBall {
+    /// None for balls created by a merge this frame
+    entity: Option<Entity>,
+    size: u8,
+    transform: Transform,
+    speed_squared: f32,
+    order: SpawnOrder,
+    alive: bool,
+}
+
+/// Balls created by a merge this frame can chain merge with balls this far apart from their
+/// final surface, since they are still growing
+const CHAIN_TOLERANCE: f32 = 0.05;
+
+/// Ordering of two candidate merges: the pair with the lowest ball goes first, then the pair
+/// with the oldest ball, then the pair whose other ball is oldest
+fn pair_key(balls: &[MergeBall], (a, b): (usize, usize)) -> (f32, SpawnOrder, SpawnOrder) {
+    let (a, b) = (&balls[a], &balls[b]);
+    (
+        a.transform.translation.y.min(b.transform.translation.y),
+        a.order.min(b.order),
+        a.order.max(b.order),
+    )
+}
+
+fn compare_pairs(balls: &[MergeBall], p: (usize, usize), q: (usize, usize)) -> std::cmp::Ordering {
+    let (p, q) = (pair_key(balls, p), pair_key(balls, q));
+    p.0.total_cmp(&q.0).then(p.1.cmp(&q.1)).then(p.2.cmp(&q.2))
+}
+
+/// Merge two balls into a new ball of the next size and return its index
+/// The new ball keeps the transform of the slower ball, or of the older ball if they are equally
+/// fast
+/// Two balls of the final tier vanish without creating a new ball
+fn merge_pair(
+    balls: &mut Vec<MergeBall>,
+    (a, b): (usize, usize),
+    counter: &mut SpawnCounter,
+    templates: &BallTemplates,
+) -> Option<usize> {
+    if balls[a].size == templates.final_tier() {
+        balls[a].alive = false;
+        balls[b].alive = false;
+        return None;
+    }
+    let (first, second) = if balls[a].order < balls[b].order {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let kept = if balls[second].speed_squared < balls[first].speed_squared {
+        second
+    } else {
+        first
+    };
+    balls[a].alive = false;
+    balls[b].alive = false;
+    balls.push(MergeBall {
+        entity: None,
+        size: balls[a].size + 1,
+        transform: balls[kept].transform,
+        speed_squared: balls[kept].speed_squared,
+        order: counter.next_order(),
+        alive: true,
+    });
+    Some(balls.len() - 1)
+}
+
+/// Find the next merge of a ball created this frame, with a ball that it touches once grown
+fn chain_partner(
+    balls: &[MergeBall],
+    new: usize,
+    templates: &BallTemplates,
+) -> Option<(usize, usize)> {
+    let ball = &balls[new];
+    let reach = templates.radius(ball.size) * 2.0 + CHAIN_TOLERANCE;
+    (0..balls.len())
+        .filter(|&other| {
+            other != new
+                && balls[other].alive
+                && balls[other].size == ball.size
+                && balls[other]
+                    .transform
+                    .translation
+                    .distance(ball.transform.translation)
+                    <= reach
+        })
+        .map(|other| (new, other))
+        .min_by(|&p, &q| compare_pairs(balls, p, q))
+}
+
+/// Resolve every merge of this frame and return the pairs of ball indexes that merged, in order
+///
+/// Candidate pairs are touching balls of the same size. They are merged in the order given by
+/// [`pair_key`], skipping pairs where a ball already merged. Right after a merge, the new ball
+/// merges again with the first ball of its size that it touches, which repeats until it touches
+/// none, before moving on to the next candidate pair. Pairs of final tier balls vanish instead.
+fn resolve_merges(
+    balls: &mut Vec<MergeBall>,
+    mut pairs: Vec<(usize, usize)>,
+    counter: &mut SpawnCounter,
+    templates: &BallTemplates,
+) -> Vec<(usize, usize)> {
+    pairs.retain(|&(a, b)| balls[a].size == balls[b].size);
+    pairs.sort_by(|&p, &q| compare_pairs(balls, p, q));
+    let mut merges = vec![];
+    for pair in pairs {
+        if !balls[pair.0].alive || !balls[pair.1].alive {
+            continue;
+        }
+        let mut next = Some(pair);
+        while let Some(pair) = next {
+            merges.push(pair);
+            next = merge_pair(balls, pair, counter, templates)
+                .and_then(|new| chain_partner(balls, new, templates));
+        }
+    }
+    merges
+}
+
+/// Merge touching balls of the same size, in a deterministic order described in
+/// [`resolve_merges`]
+/// Contacts come from the physics engine, so only pairs that actually touch are visited instead
+/// of every pair of balls. The contacts persist while the balls touch, so a pair that was skipped
+/// this frame is merged in a later one.
+#[allow(clippy::too_many_arguments)]
 pub fn merge_check(
-    mut query: Query<
-        (Entity, &mut BallSize, &mut LinearVelocity, &mut Transform),
+    query: Query<
+        (Entity, &BallSize, &LinearVelocity, &Transform, &SpawnOrder),
         Without<ExampleBall>,
     >,
+    collisions: Res<Collisions>,
+    mut counter: ResMut<SpawnCounter>,
     mut commands: Commands,
     ball_templates: Res<BallTemplates>,
     mut animations: ResMut<Assets<AnimationClip>>,
     mut scale_event: EventWriter<BoxScaleEvent>,
-    mut points: ResMut<points::GamePoints>,
+    mut merged: EventWriter<BallMerged>,
+    mut vanished: EventWriter<FinalBallsVanished>,
 ) {
-    let mut combinations = query.iter_combinations_mut();
-    let mut removed: Option<Vec<Entity>> = None;
-    while let Some([(ent1, size1, vel1, trans1), (ent2, size2, vel2, trans2)]) =
-        combinations.fetch_next()
-    {
-        if size1.0 != size2.0 {
+    let mut balls: Vec<MergeBall> = vec![];
+    let mut indexes: HashMap<Entity, usize> = HashMap::new();
+    for (ent, size, vel, trans, order) in query.iter() {
+        indexes.insert(ent, balls.len());
+        balls.push(MergeBall {
+            entity: Some(ent),
+            size: size.0,
+            transform: *trans,
+            speed_squared: vel.0.length_squared(),
+            order: *order,
+            alive: true,
+        });
+    }
+    // Contacts with walls, the example ball, and balls despawned since the last step are skipped
+    let pairs: Vec<(usize, usize)> = collisions
+        .iter()
+        .filter(|contacts| contacts.during_current_frame)
+        .filter_map(|contacts| {
+            Some((
+                *indexes.get(&contacts.entity1)?,
+                *indexes.get(&contacts.entity2)?,
+            ))
+        })
+        .collect();
+    for (a, b) in resolve_merges(&mut balls, pairs, &mut counter, &ball_templates) {
+        let size = balls[a].size;
+        let position = balls[a]
+            .transform
+            .translation
+            .lerp(balls[b].transform.translation, 0.5);
+        if size == ball_templates.final_tier() {
+            vanished.send(FinalBallsVanished { position });
             continue;
         }
-        if let Some(ref vec) = removed {
-            if vec.contains(&ent1) || vec.contains(&ent2) {
-                continue;
-            }
-        }
-        // Skip if not intersecting
-        if trans1.translation.distance_squared(trans2.translation)
-            > size1.radius() * size1.radius() * 4.0 + 0.1
+        merged.send(BallMerged {
+            size: size + 1,
+            position,
+        });
+        if let Some(growth) = ball_templates
+            .box_growth()
+            .filter(|growth| growth.tier == size + 1)
         {
-            continue;
+            let (x, y, z) = growth.size;
+            scale_event.send(BoxScaleEvent { x, y, z });
+        }
+    }
+    for ball in balls.iter() {
+        match (ball.entity, ball.alive) {
+            // Despawn merged balls
+            (Some(ent), false) => commands.entity(ent).despawn_recursive(),
+            // Spawn balls that didn't merge again this frame
+            (None, true) => spawn_merged_ball(
+                ball.size,
+                ball.transform,
+                ball.order,
+                &ball_templates,
+                &mut commands,
+                &mut animations,
+            ),
+            _ => {}
         }
-        // Choose the translation of the slower moving ball
-        let mut new_trans = if vel1.0.length_squared() < vel2.0.length_squared() {
-            trans1.clone()
-        } else {
-            trans2.clone()
-        };
-        // Despawn merged balls
-        commands.entity(ent1).despawn_recursive();
-        commands.entity(ent2).despawn_recursive();
-        if let Some(ref mut vec) = removed {
-            vec.push(ent1);
-            vec.push(ent2);
-        } else {
-            removed = Some(vec![ent1, ent2]);
+    }
+}
+
+/// Spawn the ball created by a merge, growing from the size of the balls that merged
+fn spawn_merged_ball(
+    size: u8,
+    mut new_trans: Transform,
+    order: SpawnOrder,
+    ball_templates: &BallTemplates,
+    commands: &mut Commands,
+    animations: &mut Assets<AnimationClip>,
+) {
+    let name = Name::new("combined".to_owned() + format!("{}", size).as_str());
+    let start =
+        new_trans.scale.x * ball_templates.radius(size - 1) / ball_templates.radius(size) * 0.9;
+    new_trans.scale = Vec3::from_array([start; 3]);
+    let mut new_ball = Ball::new(size, ball_templates);
+    new_ball.spatial.transform = new_trans;
+    new_ball.collider.set_scale(new_trans.scale, 0);
+    new_ball
+        .spawn(ball_templates, commands)
+        .insert((name.clone(), order))
+        .insert(growth_animation(&name, start, animations));
+}
+
+/// Animation growing the ball with this name from a scale to its full size
+pub(crate) fn growth_animation(
+    name: &Name,
+    start: f32,
+    animations: &mut Assets<AnimationClip>,
+) -> AnimationPlayer {
+    let mut player = AnimationPlayer::default();
+    let mut animation = AnimationClip::default();
+    let end = 1.0;
+    animation.add_curve_to_path(
+        EntityPath {
+            parts: vec![name.clone()],
+        },
+        VariableCurve {
+            keyframe_timestamps: vec![0.0, 1.0, 100000000.0],
+            keyframes: Keyframes::Scale(vec![
+                Vec3::new(start, start, start),
+                Vec3::new(end, end, end),
+                Vec3::new(end, end, end), //Issue with Bevy 0.12.0 animations
+                                          // ending. Will fix later
+            ]),
+        },
+    );
+    player.play(animations.add(animation)).set_speed(2.0);
+    player
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::points;
+    use crate::replay::PhysicsTick;
+
+    /// Build an app that runs physics and merging without a window
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            PhysicsPlugins::default(),
+        ))
+        .insert_resource(Time::new_with(Physics::fixed_once_hz(60.0)))
+        .insert_resource(BallTemplates::empty())
+        .init_resource::<Assets<AnimationClip>>()
+        .init_resource::<SpawnCounter>()
+        .add_event::<BoxScaleEvent>()
+        .add_event::<BallMerged>()
+        .add_event::<FinalBallsVanished>()
+        .add_systems(PostUpdate, number_balls);
+        app
+    }
+
+    /// Build an app without gravity that merges balls and scores the merges
+    /// The physics step count stays at zero, so every merge of a test is part of one combo
+    fn merge_app() -> App {
+        let mut app = headless_app();
+        app.insert_resource(Gravity(Vec3::ZERO))
+            .insert_resource(points::GamePoints(0))
+            .init_resource::<points::ScoringRules>()
+            .init_resource::<points::ComboTracker>()
+            .init_resource::<PhysicsTick>()
+            .add_event::<points::PointsEvent>()
+            .add_systems(
+                Update,
+                (merge_check, points::score_merges, points::apply_points).chain(),
+            );
+        app
+    }
+
+    /// Radius of a ball size in the builtin catalog
+    fn radius(size: u8) -> f32 {
+        BallTemplates::empty().radius(size)
+    }
+
+    /// Spawn a ball at a position without any meshes
+    fn spawn_ball(app: &mut App, size: u8, translation: Vec3) -> Entity {
+        let mut ball = Ball::new(size, app.world.resource::<BallTemplates>());
+        ball.spatial.transform.translation = translation;
+        app.world.spawn(ball).id()
+    }
+
+    /// Return the sizes of every ball in the world
+    fn ball_sizes(app: &mut App) -> Vec<u8> {
+        let mut sizes: Vec<u8> = app
+            .world
+            .query::<&BallSize>()
+            .iter(&app.world)
+            .map(|size| size.0)
+            .collect();
+        sizes.sort();
+        sizes
+    }
+
+    /// Return the size and translation of every ball created by a merge
+    fn merged_balls(app: &mut App) -> Vec<(u8, Vec3)> {
+        app.world
+            .query_filtered::<(&BallSize, &Transform), With<AnimationPlayer>>()
+            .iter(&app.world)
+            .map(|(size, trans)| (size.0, trans.translation))
+            .collect()
+    }
+
+    /// Run the frames needed for a merge: physics sets up the bodies, then finds their contacts,
+    /// then the balls merge
+    fn run_merge_frames(app: &mut App) {
+        for _ in 0..3 {
+            app.update();
         }
-        let name = Name::new("combined".to_owned() + format!("{}", size1.0 + 1).as_str());
-        let start = new_trans.scale.x * BallSize(size1.0).start_radius()
-            / BallSize(size1.0 + 1).start_radius()
-            * 0.9;
-        new_trans.scale = Vec3::from_array([start; 3]);
-        points.as_mut().0 += size1.0 as i32 * size1.0 as i32;
-        let mut new_ball = Ball::new(size1.0 + 1);
-        new_ball.spatial.transform = new_trans;
-        new_ball.collider.set_scale(new_trans.scale, 0);
-        new_ball
-            .spawn(&ball_templates, &mut commands)
-            .insert(name.clone())
-            // Growing animation
-            .insert({
-                let mut player = AnimationPlayer::default();
-                let mut animation = AnimationClip::default();
-                let end = BallSize(size1.0 + 1).scale();
-                animation.add_curve_to_path(
-                    EntityPath {
-                        parts: vec![name.clone()],
-                    },
-                    VariableCurve {
-                        keyframe_timestamps: vec![0.0, 1.0, 100000000.0],
-                        keyframes: Keyframes::Scale(vec![
-                            Vec3::new(start, start, start),
-                            Vec3::new(end, end, end),
-                            Vec3::new(end, end, end), //Issue with Bevy 0.12.0 animations
-                                                      // ending. Will fix later
-                        ]),
-                    },
-                );
-                player.play(animations.add(animation)).set_speed(2.0);
-                player
-            });
-        if size1.0 + 1 == 9 {
-            scale_event.send(BoxScaleEvent {
-                x: 4.0,
-                y: 7.0,
-                z: 6.0,
-            });
+    }
+
+    #[test]
+    fn touching_balls_merge() {
+        let mut app = merge_app();
+        let radius = radius(3);
+        spawn_ball(&mut app, 3, Vec3::new(-radius, 0.0, 0.0));
+        spawn_ball(&mut app, 3, Vec3::new(radius, 0.0, 0.0));
+        spawn_ball(&mut app, 2, Vec3::new(0.0, 10.0, 0.0));
+        run_merge_frames(&mut app);
+        assert_eq!(ball_sizes(&mut app), vec![2, 4]);
+        assert_eq!(app.world.resource::<points::GamePoints>().0, 9);
+    }
+
+    #[test]
+    fn column_of_three_merges_lowest_pair() {
+        let mut app = merge_app();
+        let diameter = radius(2) * 2.0;
+        let bottom = Vec3::ZERO;
+        spawn_ball(&mut app, 2, bottom);
+        spawn_ball(&mut app, 2, bottom + Vec3::Y * diameter);
+        let top = spawn_ball(&mut app, 2, bottom + Vec3::Y * diameter * 2.0);
+        run_merge_frames(&mut app);
+        assert_eq!(ball_sizes(&mut app), vec![2, 3]);
+        assert!(app.world.get_entity(top).is_some());
+        let merged = merged_balls(&mut app);
+        assert_eq!(merged.len(), 1);
+        assert!(merged[0].1.distance(bottom) < 1e-3);
+    }
+
+    #[test]
+    fn row_of_three_merges_oldest_pair() {
+        let mut app = merge_app();
+        let diameter = radius(2) * 2.0;
+        // Spawned right to left so the oldest ball is on the right
+        let right = Vec3::X * diameter;
+        spawn_ball(&mut app, 2, right);
+        spawn_ball(&mut app, 2, Vec3::ZERO);
+        let left = spawn_ball(&mut app, 2, -Vec3::X * diameter);
+        run_merge_frames(&mut app);
+        assert_eq!(ball_sizes(&mut app), vec![2, 3]);
+        assert!(app.world.get_entity(left).is_some());
+        let merged = merged_balls(&mut app);
+        assert_eq!(merged.len(), 1);
+        assert!(merged[0].1.distance(right) < 1e-3);
+        assert_eq!(app.world.resource::<points::GamePoints>().0, 4);
+    }
+
+    #[test]
+    fn pair_chain_merges_into_touching_larger_ball() {
+        let mut app = merge_app();
+        let small = radius(2);
+        let large = radius(3);
+        let older_large = spawn_ball(&mut app, 3, Vec3::new(0.0, 0.0, -large - small));
+        spawn_ball(&mut app, 2, Vec3::new(-small, 0.0, 0.0));
+        spawn_ball(&mut app, 2, Vec3::new(small, 0.0, 0.0));
+        run_merge_frames(&mut app);
+        // The golf balls merge into a billiards ball that merges again in the same frame, as the
+        // second merge of a combo
+        assert_eq!(ball_sizes(&mut app), vec![4]);
+        assert!(app.world.get_entity(older_large).is_none());
+        let merged = merged_balls(&mut app);
+        assert!(merged[0].1.distance(Vec3::new(0.0, 0.0, -large - small)) < 1e-3);
+        assert_eq!(app.world.resource::<points::GamePoints>().0, 4 + 9 * 2);
+    }
+
+    #[test]
+    fn square_of_four_chain_merges_into_one() {
+        let mut app = merge_app();
+        let diameter = radius(2) * 2.0;
+        let corners = [
+            Vec3::ZERO,
+            Vec3::X * diameter,
+            Vec3::Y * diameter,
+            Vec3::new(diameter, diameter, 0.0),
+        ];
+        for corner in corners {
+            spawn_ball(&mut app, 2, corner);
         }
+        run_merge_frames(&mut app);
+        // Bottom pair merges first, then the top pair, whose ball then merges with the bottom one
+        assert_eq!(ball_sizes(&mut app), vec![4]);
+        let merged = merged_balls(&mut app);
+        assert!(merged[0].1.distance(corners[0]) < 1e-3);
+        assert_eq!(
+            app.world.resource::<points::GamePoints>().0,
+            4 + 4 * 2 + 9 * 3
+        );
+        let merges: Vec<u8> = app
+            .world
+            .resource_mut::<Events<BallMerged>>()
+            .drain()
+            .map(|merge| merge.size)
+            .collect();
+        assert_eq!(merges, vec![3, 3, 4]);
+    }
+
+    #[test]
+    fn final_tier_balls_vanish() {
+        let mut app = merge_app();
+        let templates = BallTemplates::empty();
+        let final_tier = templates.final_tier();
+        let radius = radius(final_tier);
+        spawn_ball(&mut app, final_tier, Vec3::new(-radius, 0.0, 0.0));
+        spawn_ball(&mut app, final_tier, Vec3::new(radius, 0.0, 0.0));
+        run_merge_frames(&mut app);
+        assert!(ball_sizes(&mut app).is_empty());
+        assert_eq!(
+            app.world.resource::<points::GamePoints>().0,
+            templates.final_bonus()
+        );
+        assert!(app.world.resource::<Events<BallMerged>>().is_empty());
     }
 }
@@ -1,11 +1,58 @@
 mod ball;
+mod catalog;
 mod insertion;
 mod merge;
+mod queue;
 
-pub use ball::load_ball_templates;
+pub use ball::build_ball_templates;
+pub use ball::number_balls;
 pub use ball::Ball;
 pub use ball::BallSize;
 pub use ball::BallTemplates;
 pub use ball::ExampleBall;
+pub use ball::SpawnCounter;
+pub use ball::SpawnOrder;
+pub use catalog::load_ball_catalog;
+pub use catalog::BallCatalog;
+pub use catalog::BallCatalogHandle;
+pub use catalog::BallCatalogLoader;
+pub use catalog::BoxGrowth;
+pub use catalog::CATALOG_PATH;
+pub use insertion::drop_ball;
 pub use insertion::insertion_check;
+pub use insertion::spawn_example_ball;
+pub use insertion::BallDropped;
+pub(crate) use merge::growth_animation;
 pub use merge::merge_check;
+pub use merge::BallMerged;
+pub use merge::FinalBallsVanished;
+pub use queue::hold_check;
+pub use queue::spawn_queue_ui;
+pub use queue::update_queue_ui;
+pub use queue::BallQueue;
+
+use crate::state::GameState;
+use bevy::prelude::*;
+
+/// Ball catalog, merging, and the queue of upcoming balls
+pub struct BallsPlugin;
+
+impl Plugin for BallsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BallCatalog>()
+            .init_asset_loader::<BallCatalogLoader>()
+            // Templates can be inserted beforehand to run without the asset server
+            .add_systems(
+                Startup,
+                load_ball_catalog.run_if(not(resource_exists::<BallTemplates>())),
+            )
+            .add_systems(Update, build_ball_templates)
+            .add_event::<BallDropped>()
+            .add_event::<BallMerged>()
+            .add_event::<FinalBallsVanished>()
+            .init_resource::<SpawnCounter>()
+            .init_resource::<BallQueue>()
+            .add_systems(Update, merge_check.run_if(in_state(GameState::Playing)))
+            .add_systems(PostUpdate, number_balls);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::actions::{Action, ActionInput};
+use crate::balls::*;
+use crate::rng::GameRng;
+use crate::settings::Settings;
+use bevy::prelude::*;
+use rand::{Rng, RngCore};
+use std::collections::VecDeque;
+
+/// Number of upcoming balls shown to the player
+pub const QUEUE_LENGTH: usize = 3;
+
+/// Chooses the size of each new ball in the queue
+pub trait BallGenerator: Send + Sync {
+    /// Return the size of the next ball
+    fn next_size(&mut self, rng: &mut dyn RngCore) -> u8;
+}
+
+/// Generates sizes uniformly between min and max inclusive
+pub struct UniformGenerator {
+    pub min: u8,
+    pub max: u8,
+}
+
+/// Largest size of the balls given to the player by default
+pub const MAX_QUEUE_SIZE: u8 = 4;
+
+impl Default for UniformGenerator {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: MAX_QUEUE_SIZE,
+        }
+    }
+}
+
+impl BallGenerator for UniformGenerator {
+    fn next_size(&mut self, rng: &mut dyn RngCore) -> u8 {
+        rng.gen_range(self.min..=self.max)
+    }
+}
+
+/// Upcoming ball sizes and the hold slot
+#[derive(Resource)]
+pub struct BallQueue {
+    upcoming: VecDeque<u8>,
+    held: Option<u8>,
+    /// Cleared by a swap and set again by the next drop, so the player can't swap back and forth
+    can_hold: bool,
+    generator: Box<dyn BallGenerator>,
+}
+
+impl Default for BallQueue {
+    fn default() -> Self {
+        Self::new(UniformGenerator::default())
+    }
+}
+
+impl BallQueue {
+    /// Create an empty queue filled by a generator on reset
+    pub fn new(generator: impl BallGenerator + 'static) -> Self {
+        Self {
+            upcoming: VecDeque::new(),
+            held: None,
+            can_hold: true,
+            generator: Box::new(generator),
+        }
+    }
+    /// Empty the hold slot and generate a new list of upcoming balls
+    pub fn reset(&mut self, rng: &mut dyn RngCore) {
+        self.held = None;
+        self.can_hold = true;
+        self.upcoming.clear();
+        while self.upcoming.len() < QUEUE_LENGTH {
+            let size = self.generator.next_size(rng);
+            self.upcoming.push_back(size);
+        }
+    }
+    /// Take the next ball out of the queue and generate a new one at the back
+    /// Called when a ball is dropped, which allows another swap
+    pub fn pop_next(&mut self, rng: &mut dyn RngCore) -> u8 {
+        let size = self.generator.next_size(rng);
+        self.upcoming.push_back(size);
+        self.can_hold = true;
+        self.upcoming.pop_front().unwrap()
+    }
+    /// Exchange the current ball with the held one and return the new current ball
+    /// If nothing is held, the current ball is stored and the next ball of the queue is returned
+    /// Returns None if a swap was already made since the last drop
+    pub fn swap_held(&mut self, current: u8, rng: &mut dyn RngCore) -> Option<u8> {
+        if !self.can_hold {
+            return None;
+        }
+        let next = match self.held.replace(current) {
+            Some(held) => held,
+            None => self.pop_next(rng),
+        };
+        self.can_hold = false;
+        Some(next)
+    }
+    /// Sizes of the upcoming balls, next one first
+    pub fn upcoming(&self) -> impl Iterator<Item = u8> + '_ {
+        self.upcoming.iter().copied()
+    }
+    /// Size of the held ball
+    pub fn held(&self) -> Option<u8> {
+        self.held
+    }
+    /// True if the current ball can be swapped with the held ball
+    pub fn can_hold(&self) -> bool {
+        self.can_hold
+    }
+    /// Put back the upcoming balls and the hold slot of a saved round
+    pub fn restore(&mut self, upcoming: Vec<u8>, held: Option<u8>, can_hold: bool) {
+        self.upcoming = upcoming.into();
+        self.held = held;
+        self.can_hold = can_hold;
+    }
+}
+
+/// Swap the example ball with the held ball when the hold action is pressed
+pub fn hold_check(
+    actions: ActionInput,
+    mut queue: ResMut<BallQueue>,
+    example_ball: Query<(Entity, &BallSize, &Transform, &Visibility), With<ExampleBall>>,
+    ball_templates: Res<BallTemplates>,
+    mut rng: ResMut<GameRng>,
+    mut commands: Commands,
+) {
+    if !actions.just_pressed(Action::Hold) {
+        return;
+    }
+    let Ok((ent, size, trans, visibility)) = example_ball.get_single() else {
+        return;
+    };
+    let Some(new_size) = queue.swap_held(size.0, rng.as_mut()) else {
+        return;
+    };
+    commands.entity(ent).despawn_recursive();
+    spawn_example_ball(new_size, rng.as_mut(), &ball_templates, &mut commands)
+        .insert((*trans, *visibility));
+}
+
+/// Label struct for the queue ui
+#[derive(Component)]
+pub struct QueueDisplay;
+
+/// Spawn the display of the upcoming balls and the held ball
+pub fn spawn_queue_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = TextStyle {
+        font: asset_server.load("fonts/mononoki-Regular.ttf"),
+        font_size: 25.0,
+        color: Color::WHITE,
+    };
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new("Next: ", style.clone()),
+            TextSection::from_style(style.clone()),
+            TextSection::from_style(style.clone()),
+            TextSection::from_style(style.clone()),
+        ])
+        .with_text_alignment(TextAlignment::Right)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.0),
+            right: Val::Px(5.0),
+            ..default()
+        }),
+        QueueDisplay,
+    ));
+}
+
+/// Update the queue display with the names of the balls and the bindings of the hold action
+pub fn update_queue_ui(
+    mut query: Query<&mut Text, With<QueueDisplay>>,
+    queue: Res<BallQueue>,
+    ball_templates: Res<BallTemplates>,
+    settings: Res<Settings>,
+) {
+    let mut text = query.get_single_mut().unwrap();
+    text.sections[2].value = match settings.controls.prompt(Action::Hold) {
+        Some(keys) => format!("\nHold ({}): ", keys),
+        None => "\nHold: ".to_owned(),
+    };
+    text.sections[1].value = queue
+        .upcoming()
+        .map(|size| ball_templates.tier(size).name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    text.sections[3].value = match queue.held() {
+        Some(size) => ball_templates.tier(size).name.clone(),
+        None => "empty".to_owned(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    /// Generates 1, 2, 3, ... so the queue order is easy to follow
+    struct CountingGenerator(u8);
+
+    impl BallGenerator for CountingGenerator {
+        fn next_size(&mut self, _rng: &mut dyn RngCore) -> u8 {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    #[test]
+    fn queue_pops_in_order() {
+        let mut rng = StepRng::new(0, 1);
+        let mut queue = BallQueue::new(CountingGenerator(0));
+        queue.reset(&mut rng);
+        assert_eq!(queue.upcoming().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(queue.pop_next(&mut rng), 1);
+        assert_eq!(queue.upcoming().collect::<Vec<_>>(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn hold_swaps_once_per_drop() {
+        let mut rng = StepRng::new(0, 1);
+        let mut queue = BallQueue::new(CountingGenerator(0));
+        queue.reset(&mut rng);
+        // Holding with an empty slot takes the next ball of the queue
+        assert_eq!(queue.swap_held(7, &mut rng), Some(1));
+        assert_eq!(queue.held(), Some(7));
+        assert_eq!(queue.swap_held(1, &mut rng), None);
+        queue.pop_next(&mut rng);
+        assert_eq!(queue.swap_held(2, &mut rng), Some(7));
+        assert_eq!(queue.held(), Some(2));
+    }
+}
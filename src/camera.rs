@@ -2,50 +2,246 @@ use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use std::f32::consts::PI;
 
-use crate::input::OrbitUpdate;
+use crate::actions::{Action, ActionInput};
+use crate::input::{OrbitUpdate, ZoomUpdate};
+use crate::settings::Settings;
+use crate::setup::BoxSize;
 
-#[derive(Component)]
+/// Camera orbiting around the box
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_camera).add_systems(
+            Update,
+            (
+                orbit_camera,
+                zoom_camera.run_if(on_event::<ZoomUpdate>()),
+                view_input,
+                frame_camera,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Yaw and pitch of the camera around the box
+#[derive(Component, Clone, Copy)]
 pub struct CameraAngle(f32, f32);
 
-#[derive(Resource)]
-pub struct IsOrbiting(bool);
+/// Where the camera is turning, and how fast it keeps orbiting after a flick
+#[derive(Component)]
+pub struct CameraOrbit {
+    /// Angle the camera eases to
+    target: CameraAngle,
+    /// Yaw and pitch per second, slowed down by the orbit damping once nothing orbits
+    velocity: Vec2,
+}
+
+/// How far the camera is from the center of the box
+/// The distance follows the zoom times the closest distance that shows the whole box, so the
+/// camera backs off when the box grows or the window gets narrower
+#[derive(Component)]
+pub struct CameraFraming {
+    /// Multiple of the closest distance showing the whole box, between 1 and `MAX_ZOOM_OUT`
+    pub zoom: f32,
+    distance: f32,
+}
 
+/// Starting distance of the camera
 const CAMERA_DIST: f32 = 30.0;
 
-pub fn new_camera() -> (Camera3dBundle, CameraAngle) {
+/// Starting zoom, about the starting distance on a wide window
+const DEFAULT_ZOOM: f32 = 1.4;
+
+/// How many times farther than the closest distance showing the whole box the camera can zoom out
+const MAX_ZOOM_OUT: f32 = 2.5;
+
+/// Rate at which the camera eases to its distance, per second
+const FRAMING_SPEED: f32 = 4.0;
+
+/// Rate at which the camera eases to its target angle, per second
+const ANGLE_SPEED: f32 = 12.0;
+
+/// Share of the latest orbit speed kept each frame while orbiting, smoothing out uneven motion
+const VELOCITY_SMOOTHING: f32 = 0.5;
+
+pub fn new_camera() -> (Camera3dBundle, CameraAngle, CameraOrbit, CameraFraming) {
+    let angle = front_view();
+    let mut transform = Transform::default();
+    place_camera(&mut transform, &angle, CAMERA_DIST);
     (
         Camera3dBundle {
-            transform: Transform::from_xyz(CAMERA_DIST, 0., 0.).looking_at(Vec3::ZERO, Vec3::Y),
+            transform,
             ..default()
         },
-        CameraAngle(0.0, 0.0),
+        angle,
+        CameraOrbit {
+            target: angle,
+            velocity: Vec2::ZERO,
+        },
+        CameraFraming {
+            zoom: DEFAULT_ZOOM,
+            distance: CAMERA_DIST,
+        },
     )
 }
 
-/// Orbit camera event on mouse move, only active while clicked
+/// Looking from the positive x side
+fn front_view() -> CameraAngle {
+    CameraAngle(PI / 2.0, 0.0)
+}
+
+/// Looking straight down into the box
+fn top_view() -> CameraAngle {
+    CameraAngle(PI / 2.0, -PI / 2.0)
+}
+
+/// Looking down at a corner of the box, along the diagonal of a cube
+fn isometric_view() -> CameraAngle {
+    CameraAngle(PI * 3.0 / 4.0, -(0.5f32.sqrt()).atan())
+}
+
+/// Move the camera to its angle and distance, looking at the center of the box
+fn place_camera(transform: &mut Transform, angle: &CameraAngle, distance: f32) {
+    transform.rotation = Quat::from_euler(EulerRot::YXZ, angle.0, angle.1, 0.0);
+    let rot_matrix = Mat3::from_quat(transform.rotation);
+    transform.translation = rot_matrix.mul_vec3(Vec3::new(0.0, 0.0, distance));
+}
+
+/// Closest distance at which the whole box is in view from any angle, for a window aspect ratio
+pub fn fit_distance(box_size: &BoxSize, projection: &Projection, aspect: f32) -> f32 {
+    let fov = match projection {
+        Projection::Perspective(perspective) => perspective.fov,
+        Projection::Orthographic(_) => PI / 4.0,
+    };
+    let half_vertical = fov / 2.0;
+    let half_horizontal = (half_vertical.tan() * aspect).atan();
+    // Sphere around the box, so no corner leaves the view while orbiting
+    let radius = Vec3::new(box_size.x, box_size.y, box_size.z).length();
+    radius / half_vertical.min(half_horizontal).sin()
+}
+
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn(new_camera());
+}
+
+/// Orbit the camera by all orbit events of the frame, and keep it orbiting after they stop
+/// The speed and direction follow the camera settings, and the orbit slows down by the damping
+/// of the settings once nothing orbits
 pub fn orbit_camera(
-    mut event: EventReader<OrbitUpdate>,
+    mut events: EventReader<OrbitUpdate>,
+    settings: Res<Settings>,
     window: Query<&Window, With<PrimaryWindow>>,
-    mut query: Query<(&mut Transform, &mut CameraAngle)>,
+    time: Res<Time>,
+    mut query: Query<&mut CameraOrbit>,
 ) {
-    let Some(OrbitUpdate {
-        delta: rotation_move,
-    }) = event.read().next().cloned()
-    else {
+    let orbiting = !events.is_empty();
+    let rotation_move: Vec2 = events.read().map(|event| event.delta).sum();
+    let Ok(window) = window.get_single() else {
         return;
     };
-    event.clear();
-    let window = window.get_single().unwrap();
-    for (mut transform, mut pos) in query.iter_mut() {
-        if rotation_move.length_squared() > 0.0 {
-            let delta_x = rotation_move.x / window.width() * PI * 2.0;
-            let delta_y = rotation_move.y / window.height() * PI;
-            pos.0 -= delta_x;
-            pos.1 -= delta_y;
-            pos.1 = pos.1.clamp(-PI / 2.0, PI / 2.0);
-            transform.rotation = Quat::from_euler(EulerRot::YXZ, pos.0, pos.1, 0.0);
-            let rot_matrix = Mat3::from_quat(transform.rotation);
-            transform.translation = rot_matrix.mul_vec3(Vec3::new(0.0, 0.0, CAMERA_DIST));
+    let dt = time.delta_seconds();
+    let camera = &settings.camera;
+    let invert = |inverted: bool| if inverted { -1.0 } else { 1.0 };
+    let delta = Vec2::new(
+        rotation_move.x / window.width() * PI * 2.0 * invert(camera.invert_x),
+        rotation_move.y / window.height() * PI * invert(camera.invert_y),
+    ) * camera.orbit_sensitivity;
+    for mut orbit in query.iter_mut() {
+        let delta = if orbiting {
+            // Holding still while dragging stops the orbit
+            if dt > 0.0 {
+                orbit.velocity = orbit.velocity.lerp(delta / dt, 1.0 - VELOCITY_SMOOTHING);
+            }
+            delta
+        } else {
+            orbit.velocity *= (-camera.orbit_damping * dt).exp();
+            if orbit.velocity.length() < 0.01 {
+                orbit.velocity = Vec2::ZERO;
+            }
+            orbit.velocity * dt
+        };
+        if delta == Vec2::ZERO {
+            continue;
+        }
+        orbit.target.0 -= delta.x;
+        orbit.target.1 = (orbit.target.1 - delta.y).clamp(-PI / 2.0, PI / 2.0);
+    }
+}
+
+/// Zoom the camera by the zoom events, between the closest distance that shows the whole box and
+/// a few times farther
+pub fn zoom_camera(mut events: EventReader<ZoomUpdate>, mut query: Query<&mut CameraFraming>) {
+    let factor: f32 = events.read().map(|zoom| zoom.factor).product();
+    for mut framing in query.iter_mut() {
+        framing.zoom = (framing.zoom * factor).clamp(1.0, MAX_ZOOM_OUT);
+    }
+}
+
+/// Turn to the front, top-down or isometric view with the keys and buttons of the view actions
+pub fn view_input(actions: ActionInput, mut query: Query<(&CameraAngle, &mut CameraOrbit)>) {
+    let view = [
+        (Action::FrontView, front_view()),
+        (Action::TopView, top_view()),
+        (Action::IsometricView, isometric_view()),
+    ]
+    .into_iter()
+    .find(|(action, _)| actions.just_pressed(*action));
+    if let Some((_, CameraAngle(yaw, pitch))) = view {
+        for (angle, mut orbit) in query.iter_mut() {
+            // Turn the short way around
+            let yaw_diff = (yaw - angle.0 + PI).rem_euclid(PI * 2.0) - PI;
+            orbit.target = CameraAngle(angle.0 + yaw_diff, pitch);
+            orbit.velocity = Vec2::ZERO;
         }
     }
 }
+
+/// Ease the camera to its distance for the box size and window, and to its target angle
+pub fn frame_camera(
+    box_size: Res<BoxSize>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    time: Res<Time>,
+    mut query: Query<(
+        &mut Transform,
+        &mut CameraAngle,
+        &CameraOrbit,
+        &mut CameraFraming,
+        &Projection,
+    )>,
+) {
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+    let aspect = window.width() / window.height().max(1.0);
+    let ease = |speed: f32| 1.0 - (-speed * time.delta_seconds()).exp();
+    for (mut transform, mut angle, orbit, mut framing, projection) in query.iter_mut() {
+        let target = fit_distance(&box_size, projection, aspect) * framing.zoom;
+        framing.distance += (target - framing.distance) * ease(FRAMING_SPEED);
+        angle.0 += (orbit.target.0 - angle.0) * ease(ANGLE_SPEED);
+        angle.1 += (orbit.target.1 - angle.1) * ease(ANGLE_SPEED);
+        place_camera(&mut transform, &angle, framing.distance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_windows_need_a_farther_camera() {
+        let projection = Projection::default();
+        let box_size = BoxSize::default();
+        let wide = fit_distance(&box_size, &projection, 16.0 / 9.0);
+        let narrow = fit_distance(&box_size, &projection, 9.0 / 16.0);
+        assert!((wide * DEFAULT_ZOOM - CAMERA_DIST).abs() < 1.0);
+        assert!(narrow > wide);
+        let grown = BoxSize {
+            x: 4.0,
+            y: 7.0,
+            z: 6.0,
+        };
+        assert!(fit_distance(&grown, &projection, 16.0 / 9.0) > wide);
+    }
+}
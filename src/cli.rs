@@ -0,0 +1,34 @@
+/// Return the value following a flag on the command line, like `--seed 42`
+pub fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag).skip(1);
+    args.next()
+}
+
+/// Whether a flag is on the command line, with or without a value
+pub fn has_flag(flag: &str) -> bool {
+    std::env::args().any(|arg| arg == flag)
+}
+
+/// Read the seed from the `--seed` command line flag
+#[cfg(not(target_arch = "wasm32"))]
+pub fn launch_seed() -> Option<u64> {
+    parse_seed(&flag_value("--seed")?)
+}
+
+/// Read the seed from the `seed` query parameter of the page URL
+#[cfg(target_arch = "wasm32")]
+pub fn launch_seed() -> Option<u64> {
+    let search = web_sys::window()?.location().search().ok()?;
+    let arg = web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get("seed")?;
+    parse_seed(&arg)
+}
+
+fn parse_seed(arg: &str) -> Option<u64> {
+    let seed = arg.parse().ok();
+    if seed.is_none() {
+        eprintln!("Ignoring seed {:?}, it must be a whole number", arg);
+    }
+    seed
+}
@@ -0,0 +1,176 @@
+use crate::balls::{self, BallSize, BallTemplates, ExampleBall};
+use crate::replay::{PhysicsTick, TICK_HZ};
+use crate::settings::Settings;
+use crate::setup::{BoxSize, NewGameEvent};
+use crate::state::GameState;
+use crate::stats::{RoundOutcome, RoundStats};
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+/// Danger line near the top of the box, ending the round when balls stay above it
+pub struct DangerPlugin;
+
+impl Plugin for DangerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DangerRules>()
+            .init_resource::<DangerZone>()
+            .add_systems(
+                Update,
+                danger_check
+                    .after(balls::merge_check)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, reset_danger.run_if(on_event::<NewGameEvent>()));
+    }
+}
+
+/// Rules of the danger line and its countdown
+#[derive(Resource, Clone, Debug)]
+pub struct DangerRules {
+    /// Distance of the danger line below the top of the box
+    pub margin: f32,
+    /// Balls moving slower than this are settled, so balls that were just dropped don't count
+    pub settled_speed: f32,
+    /// Seconds balls stay above the line before the warning shows
+    pub warning_delay: f32,
+    /// Seconds balls stay above the line before the round ends
+    pub countdown: f32,
+}
+
+impl Default for DangerRules {
+    fn default() -> Self {
+        Self {
+            margin: 1.0,
+            settled_speed: 1.0,
+            warning_delay: 0.5,
+            countdown: 3.5,
+        }
+    }
+}
+
+impl DangerRules {
+    /// Height of the danger line for a box size
+    pub fn height(&self, box_size: &BoxSize) -> f32 {
+        box_size.y - self.margin
+    }
+}
+
+/// How long settled balls have been above the danger line
+/// Counted in physics steps, so the countdown stops while paused and replays end the same way
+#[derive(Resource, Default)]
+pub struct DangerZone {
+    /// Physics step at which a settled ball first stayed above the line
+    over_since: Option<u32>,
+    /// Seconds since then
+    seconds_over: f32,
+}
+
+impl DangerZone {
+    /// Seconds left before the round ends, if the warning is showing
+    pub fn countdown(&self, rules: &DangerRules) -> Option<f32> {
+        self.over_since?;
+        (self.seconds_over >= rules.warning_delay)
+            .then(|| (rules.countdown - self.seconds_over).max(0.0))
+    }
+}
+
+fn reset_danger(mut zone: ResMut<DangerZone>) {
+    *zone = DangerZone::default();
+}
+
+/// Track settled balls above the danger line and end the round when the countdown runs out
+#[allow(clippy::too_many_arguments)]
+pub fn danger_check(
+    balls: Query<(&Transform, &BallSize, &LinearVelocity), Without<ExampleBall>>,
+    templates: Res<BallTemplates>,
+    box_size: Res<BoxSize>,
+    rules: Res<DangerRules>,
+    tick: Res<PhysicsTick>,
+    mut zone: ResMut<DangerZone>,
+    mut stats: ResMut<RoundStats>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let height = rules.height(&box_size);
+    let over = balls.iter().any(|(trans, size, velocity)| {
+        trans.translation.y + templates.radius(size.0) > height
+            && velocity.length() < rules.settled_speed
+    });
+    if !over {
+        zone.over_since = None;
+        zone.seconds_over = 0.0;
+        return;
+    }
+    let since = *zone.over_since.get_or_insert(tick.get());
+    zone.seconds_over = (tick.get() - since) as f32 / TICK_HZ as f32;
+    if zone.seconds_over >= rules.countdown {
+        stats.outcome = Some(RoundOutcome::Overflowed);
+        next_state.set(GameState::GameOver);
+    }
+}
+
+/// Label struct for the countdown text
+#[derive(Component)]
+pub struct DangerDisplay;
+
+/// Spawn the countdown text, hidden until the warning shows
+pub fn spawn_danger_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Px(40.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/mononoki-Bold.ttf"),
+                        font_size: 40.0,
+                        color: Color::rgb(1.0, 0.3, 0.3),
+                    },
+                ),
+                DangerDisplay,
+            ));
+        });
+}
+
+/// Draw the danger line around the box, flashing while the warning shows unless flashing is
+/// reduced, and show the countdown
+#[allow(clippy::too_many_arguments)]
+pub fn show_danger(
+    mut gizmos: Gizmos,
+    mut query: Query<&mut Text, With<DangerDisplay>>,
+    zone: Res<DangerZone>,
+    rules: Res<DangerRules>,
+    box_size: Res<BoxSize>,
+    state: Res<State<GameState>>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    let in_round = matches!(state.get(), GameState::Playing | GameState::Paused);
+    let flash = !settings.accessibility.reduce_flashing;
+    let countdown = zone.countdown(&rules).filter(|_| in_round);
+    if in_round {
+        let color = match countdown {
+            // Flash twice a second
+            Some(_) if !flash || (time.elapsed_seconds() * 2.0).fract() < 0.5 => Color::RED,
+            Some(_) => Color::rgb(1.0, 0.6, 0.2),
+            None => Color::rgba(1.0, 0.2, 0.2, 0.4),
+        };
+        let height = rules.height(&box_size);
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .map(|(x, z)| Vec3::new(x * box_size.x, height, z * box_size.z));
+        gizmos.linestrip(corners.into_iter().chain([corners[0]]), color);
+    }
+    let mut text = query.single_mut();
+    text.sections[0].value = match countdown {
+        Some(seconds) => format!("Overflow! {:.1}", seconds),
+        None => String::new(),
+    };
+}
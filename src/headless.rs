@@ -0,0 +1,193 @@
+use crate::balls::{self, BallCatalog, BallDropped, BallSize, BallTemplates, ExampleBall};
+use crate::points::GamePoints;
+use crate::replay::{self, ReplayPlayer};
+use crate::rng::GameRng;
+use crate::setup::{BoxSize, NewGameEvent};
+use crate::state::GameState;
+use crate::stats::{RoundOutcome, RoundStats};
+use crate::{BallBlitzConfig, BallBlitzPlugin};
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_xpbd_3d::prelude::*;
+use rand::Rng;
+use std::time::Duration;
+
+/// Physics steps between two drops of the bot
+const DROP_INTERVAL: u32 = 60;
+
+/// Games that last longer than this many physics steps are ended without an outcome
+const MAX_STEPS: u32 = 60 * 60 * 30;
+
+/// Progress of the headless games
+#[derive(Resource)]
+struct HeadlessRun {
+    games: u32,
+    finished: u32,
+    /// Physics steps since the start of the current game
+    steps: u32,
+    scores: Vec<i32>,
+}
+
+/// Play games without a window, as fast as possible, and print the statistics of each game
+///
+/// Every frame runs exactly one physics step of 1/60th of a second, so games are not tied to real
+/// time. A bot drops the next ball at a random position every second, unless the config has a
+/// replay, in which case each game plays the drops of the replay. The ball catalog is read from
+/// the given path, or from the assets directory.
+pub fn run(games: u32, catalog_path: Option<String>, config: BallBlitzConfig) {
+    let catalog_path = catalog_path.unwrap_or_else(|| format!("assets/{}", balls::CATALOG_PATH));
+    let catalog = match BallCatalog::read(&catalog_path) {
+        Ok(catalog) => catalog,
+        Err(err) => {
+            eprintln!("Could not load {}: {}", catalog_path, err);
+            std::process::exit(1);
+        }
+    };
+    App::new()
+        .add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            TransformPlugin,
+            HierarchyPlugin,
+            AnimationPlugin,
+            PhysicsPlugins::default(),
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / replay::TICK_HZ,
+        )))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>()
+        .insert_resource(BallTemplates::without_meshes(catalog))
+        .add_plugins(BallBlitzPlugin {
+            config: BallBlitzConfig {
+                headless: true,
+                ..config
+            },
+        })
+        .insert_resource(HeadlessRun {
+            games,
+            finished: 0,
+            steps: 0,
+            scores: vec![],
+        })
+        .add_systems(OnEnter(GameState::Menu), start_first_game)
+        .add_systems(Update, reset_steps.run_if(on_event::<NewGameEvent>()))
+        .add_systems(
+            Update,
+            (
+                drop_bot
+                    .before(balls::merge_check)
+                    .run_if(not(resource_exists::<ReplayPlayer>())),
+                end_long_game,
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(OnEnter(GameState::GameOver), finish_game)
+        .run();
+}
+
+fn start_first_game(mut new_game: EventWriter<NewGameEvent>) {
+    new_game.send(NewGameEvent);
+}
+
+fn reset_steps(mut run: ResMut<HeadlessRun>) {
+    run.steps = 0;
+}
+
+/// Drop the next ball at a random position over the box every [`DROP_INTERVAL`] steps
+#[allow(clippy::too_many_arguments)]
+fn drop_bot(
+    run: Res<HeadlessRun>,
+    example_ball: Query<(Entity, &BallSize, &Transform), With<ExampleBall>>,
+    ball_templates: Res<BallTemplates>,
+    box_size: Res<BoxSize>,
+    mut queue: ResMut<balls::BallQueue>,
+    mut rng: ResMut<GameRng>,
+    mut commands: Commands,
+    mut dropped: EventWriter<BallDropped>,
+) {
+    if run.steps % DROP_INTERVAL != DROP_INTERVAL - 1 {
+        return;
+    }
+    let Ok((ent, size, trans)) = example_ball.get_single() else {
+        return;
+    };
+    let reach_x = box_size.x - ball_templates.radius(size.0) - 0.05;
+    let reach_z = box_size.z - ball_templates.radius(size.0) - 0.05;
+    let position = Vec3::new(
+        rng.gen_range(-reach_x..=reach_x),
+        box_size.y,
+        rng.gen_range(-reach_z..=reach_z),
+    );
+    balls::drop_ball(
+        BallDropped {
+            size: size.0,
+            position,
+            rotation: trans.rotation,
+        },
+        &ball_templates,
+        &mut commands,
+        &mut dropped,
+    );
+    let new_size = queue.pop_next(rng.as_mut());
+    commands.entity(ent).despawn_recursive();
+    balls::spawn_example_ball(new_size, rng.as_mut(), &ball_templates, &mut commands);
+}
+
+/// Count the steps of the game and end it after [`MAX_STEPS`]
+fn end_long_game(mut run: ResMut<HeadlessRun>, mut next_state: ResMut<NextState<GameState>>) {
+    run.steps += 1;
+    if run.steps >= MAX_STEPS {
+        next_state.set(GameState::GameOver);
+    }
+}
+
+/// Print the statistics of the game that ended, then start the next one or exit
+fn finish_game(
+    mut run: ResMut<HeadlessRun>,
+    stats: Res<RoundStats>,
+    points: Res<GamePoints>,
+    rng: Res<GameRng>,
+    ball_templates: Res<BallTemplates>,
+    mut new_game: EventWriter<NewGameEvent>,
+    mut exit: EventWriter<AppExit>,
+) {
+    run.finished += 1;
+    run.scores.push(points.0);
+    let outcome = match stats.outcome {
+        Some(RoundOutcome::Won) => "won",
+        Some(RoundOutcome::Lost) => "lost",
+        Some(RoundOutcome::Overflowed) => "overflowed",
+        None => "timed out",
+    };
+    let largest = match stats.largest_ball {
+        0 => "none".to_owned(),
+        size => format!("{} ({})", ball_templates.tier(size).name, size),
+    };
+    let overflow = match stats.drops_until_overflow {
+        Some(drops) => drops.to_string(),
+        None => "never".to_owned(),
+    };
+    println!(
+        "Game {}: {}, seed {}, score {}, largest ball {}, drops {}, drops until overflow {}, \
+         merges {}, balls lost {}, game time {}",
+        run.finished,
+        outcome,
+        rng.seed(),
+        points.0,
+        largest,
+        stats.balls_dropped,
+        overflow,
+        stats.merges.values().sum::<u32>(),
+        stats.balls_lost,
+        stats.time_played_text(),
+    );
+    if run.finished < run.games {
+        new_game.send(NewGameEvent);
+    } else {
+        let mean = run.scores.iter().sum::<i32>() as f32 / run.scores.len() as f32;
+        println!("Average score over {} games: {:.1}", run.finished, mean);
+        exit.send(AppExit);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::actions::{Action, ActionInput, ActionMap};
+use crate::balls::BallTemplates;
+use crate::points::GamePoints;
+use crate::replay::ReplayPlayer;
+use crate::rng::GameRng;
+use crate::settings::Settings;
+use crate::state::{self, GameState, StateScreen};
+use crate::stats::RoundStats;
+use crate::storage;
+use crate::widgets;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Number of scores kept in the table
+pub const MAX_HIGH_SCORES: usize = 10;
+
+/// Name of the stored high score file
+const HIGH_SCORES_FILE: &str = "high_scores.ron";
+
+/// Seconds the player has to press the clear key a second time to clear the high scores
+const CLEAR_CONFIRM_TIME: f32 = 3.0;
+
+/// One finished round in the high score table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub score: i32,
+    /// Seconds since the Unix epoch when the round ended
+    pub date: u64,
+    pub seed: u64,
+    /// Largest ball of the round, 0 if no ball was dropped
+    pub max_tier: u8,
+    /// Seconds played
+    pub duration: f32,
+}
+
+/// Best scores, highest first, kept between launches
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HighScores {
+    pub scores: Vec<HighScore>,
+    /// Position of the score of the last round, if it made the table
+    #[serde(skip)]
+    pub last_rank: Option<usize>,
+    /// Elapsed time until which a second press of C clears the table
+    #[serde(skip)]
+    pub clear_deadline: Option<f32>,
+}
+
+impl HighScores {
+    /// Read the stored table, or start an empty one
+    pub fn load() -> Self {
+        let Some(text) = storage::read(HIGH_SCORES_FILE) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable high scores: {}", err);
+            Self::default()
+        })
+    }
+    /// Store the table
+    pub fn save(&self) {
+        let result = ron::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|text| storage::write(HIGH_SCORES_FILE, &text));
+        if let Err(err) = result {
+            warn!("Could not save high scores: {}", err);
+        }
+    }
+    /// Add a score to the table and return its position, or None if it isn't high enough
+    /// Ties keep the older score first
+    pub fn insert(&mut self, score: HighScore) -> Option<usize> {
+        let rank = self
+            .scores
+            .iter()
+            .position(|other| score.score > other.score)
+            .unwrap_or(self.scores.len());
+        if rank >= MAX_HIGH_SCORES {
+            return None;
+        }
+        self.scores.insert(rank, score);
+        self.scores.truncate(MAX_HIGH_SCORES);
+        Some(rank)
+    }
+    /// Remove every score, from the table and from storage
+    pub fn clear(&mut self) {
+        *self = Self::default();
+        if let Err(err) = storage::remove(HIGH_SCORES_FILE) {
+            warn!("Could not clear high scores: {}", err);
+        }
+    }
+}
+
+/// Stored table of the best rounds, shown on the title and end screens
+pub struct HighScoresPlugin;
+
+impl Plugin for HighScoresPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HighScores::load())
+            .add_systems(
+                OnEnter(GameState::GameOver),
+                record_high_score
+                    .before(state::spawn_game_over_screen)
+                    .run_if(not(resource_exists::<ReplayPlayer>())),
+            )
+            .add_systems(
+                OnEnter(GameState::Menu),
+                spawn_high_score_table.after(state::spawn_menu_screen),
+            )
+            .add_systems(
+                OnEnter(GameState::GameOver),
+                spawn_high_score_table.after(state::spawn_game_over_screen),
+            )
+            .add_systems(
+                Update,
+                update_high_score_table.run_if(
+                    resource_changed::<HighScores>().or_else(resource_changed::<Settings>()),
+                ),
+            );
+    }
+}
+
+/// Add the score of the round that ended to the table
+pub fn record_high_score(
+    mut high_scores: ResMut<HighScores>,
+    points: Res<GamePoints>,
+    stats: Res<RoundStats>,
+    rng: Res<GameRng>,
+) {
+    high_scores.last_rank = high_scores.insert(HighScore {
+        score: points.0,
+        date: storage::unix_time(),
+        seed: rng.seed(),
+        max_tier: stats.largest_ball,
+        duration: stats.time_played,
+    });
+    if high_scores.last_rank.is_some() {
+        high_scores.save();
+    }
+}
+
+/// Clear the high scores by pressing a key of the clear action or clicking the clear button twice
+/// from the title or end screen
+/// The first press asks for confirmation, which expires after `CLEAR_CONFIRM_TIME`
+pub fn clear_high_scores_input(
+    actions: ActionInput,
+    button: Query<&Interaction, (With<ClearHighScoresButton>, Changed<Interaction>)>,
+    time: Res<Time>,
+    mut high_scores: ResMut<HighScores>,
+) {
+    let now = time.elapsed_seconds();
+    let confirming = high_scores
+        .clear_deadline
+        .is_some_and(|deadline| now < deadline);
+    let clicked = button
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    if (actions.just_pressed(Action::ClearHighScores) || clicked) && !high_scores.scores.is_empty()
+    {
+        if confirming {
+            high_scores.clear();
+        } else {
+            high_scores.clear_deadline = Some(now + CLEAR_CONFIRM_TIME);
+        }
+    } else if high_scores.clear_deadline.is_some() && !confirming {
+        high_scores.clear_deadline = None;
+    }
+}
+
+/// Label struct for the text of the high score table
+#[derive(Component)]
+pub struct HighScoreTable;
+
+/// Label struct for the button clearing the high scores
+#[derive(Component)]
+pub struct ClearHighScoresButton;
+
+/// Spawn the table on the right of the screen of the current state, with a clear button under
+/// it if there are scores to clear
+pub fn spawn_high_score_table(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>,
+    templates: Res<BallTemplates>,
+    settings: Res<Settings>,
+    screen: Query<Entity, With<StateScreen>>,
+) {
+    let Ok(screen) = screen.get_single() else {
+        return;
+    };
+    let style = TextStyle {
+        font: asset_server.load("fonts/mononoki-Regular.ttf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+    commands.entity(screen).with_children(|parent| {
+        parent
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Percent(3.0),
+                    top: Val::Percent(25.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::FlexStart,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|table| {
+                table.spawn((
+                    TextBundle::from_section(
+                        table_text(&high_scores, &templates, &settings.controls),
+                        style.clone(),
+                    ),
+                    HighScoreTable,
+                ));
+                if !high_scores.scores.is_empty() {
+                    widgets::spawn_button(
+                        table,
+                        &style,
+                        clear_button_text(&high_scores),
+                        ClearHighScoresButton,
+                    );
+                }
+            });
+    });
+}
+
+/// Rewrite the table and the clear button when the high scores or the bindings change
+/// The button is hidden while there are no scores to clear
+#[allow(clippy::type_complexity)]
+pub fn update_high_score_table(
+    high_scores: Res<HighScores>,
+    templates: Option<Res<BallTemplates>>,
+    settings: Res<Settings>,
+    mut tables: Query<&mut Text, With<HighScoreTable>>,
+    mut buttons: Query<(&mut Style, &Children), With<ClearHighScoresButton>>,
+    mut button_texts: Query<&mut Text, Without<HighScoreTable>>,
+) {
+    let Some(templates) = templates else {
+        return;
+    };
+    let text = table_text(&high_scores, &templates, &settings.controls);
+    for mut table in tables.iter_mut() {
+        table.sections[0].value = text.clone();
+    }
+    for (mut style, children) in buttons.iter_mut() {
+        style.display = if high_scores.scores.is_empty() {
+            Display::None
+        } else {
+            Display::Flex
+        };
+        for child in children.iter() {
+            if let Ok(mut text) = button_texts.get_mut(*child) {
+                text.sections[0].value = clear_button_text(&high_scores).to_owned();
+            }
+        }
+    }
+}
+
+/// Text of the clear button, which asks for a second click while confirming
+fn clear_button_text(high_scores: &HighScores) -> &'static str {
+    if high_scores.clear_deadline.is_some() {
+        "Click again to clear"
+    } else {
+        "Clear high scores"
+    }
+}
+
+/// One line per high score, with the score of the last round marked
+fn table_text(high_scores: &HighScores, templates: &BallTemplates, map: &ActionMap) -> String {
+    let mut text = "High scores\n".to_owned();
+    if high_scores.scores.is_empty() {
+        text.push_str("No rounds played yet\n");
+    }
+    for (rank, score) in high_scores.scores.iter().enumerate() {
+        let marker = if high_scores.last_rank == Some(rank) {
+            '>'
+        } else {
+            ' '
+        };
+        let largest = match score.max_tier {
+            0 => "-",
+            size => templates.tier(size).name.as_str(),
+        };
+        let seconds = score.duration as u32;
+        text.push_str(&format!(
+            "{}{:2}. {:6}  {}  {:4}:{:02}  {:14}  seed {}\n",
+            marker,
+            rank + 1,
+            score.score,
+            date_text(score.date),
+            seconds / 60,
+            seconds % 60,
+            largest,
+            score.seed,
+        ));
+    }
+    let press = match map.prompt(Action::ClearHighScores) {
+        Some(keys) => format!("Press {} or click", keys),
+        None => "Click".to_owned(),
+    };
+    if high_scores.clear_deadline.is_some() {
+        text.push_str(&format!(
+            "\n{} the button again to clear high scores",
+            press
+        ));
+    } else if !high_scores.scores.is_empty() {
+        text.push_str(&format!(
+            "\n{} the button twice to clear high scores",
+            press
+        ));
+    }
+    text
+}
+
+/// Format seconds since the Unix epoch as a UTC date
+fn date_text(unix_time: u64) -> String {
+    // Civil from days, from https://howardhinnant.github.io/date_algorithms.html
+    let days = (unix_time / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Rebinding;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn score(score: i32) -> HighScore {
+        HighScore {
+            score,
+            date: 0,
+            seed: 0,
+            max_tier: 1,
+            duration: 0.0,
+        }
+    }
+
+    #[test]
+    fn insert_keeps_the_best_scores_in_order() {
+        let mut high_scores = HighScores::default();
+        for points in 0..MAX_HIGH_SCORES as i32 {
+            high_scores.insert(score(points * 10));
+        }
+        assert_eq!(high_scores.insert(score(-5)), None);
+        assert_eq!(high_scores.insert(score(45)), Some(5));
+        assert_eq!(high_scores.scores.len(), MAX_HIGH_SCORES);
+        assert_eq!(high_scores.scores[0].score, 90);
+        assert_eq!(high_scores.scores.last().unwrap().score, 10);
+    }
+
+    /// World with one high score and no input, ready to run `clear_high_scores_input`
+    fn clear_world() -> World {
+        let mut world = World::new();
+        let mut high_scores = HighScores::default();
+        high_scores.insert(score(10));
+        world.insert_resource(high_scores);
+        world.init_resource::<Input<KeyCode>>();
+        world.init_resource::<Input<MouseButton>>();
+        world.init_resource::<Gamepads>();
+        world.init_resource::<Input<GamepadButton>>();
+        world.init_resource::<Settings>();
+        world.init_resource::<Rebinding>();
+        world.init_resource::<Time>();
+        world
+    }
+
+    #[test]
+    fn first_press_of_c_asks_for_confirmation() {
+        let mut world = clear_world();
+        world.resource_mut::<Input<KeyCode>>().press(KeyCode::C);
+        world.run_system_once(clear_high_scores_input);
+        let high_scores = world.resource::<HighScores>();
+        assert_eq!(high_scores.scores.len(), 1);
+        assert_eq!(high_scores.clear_deadline, Some(CLEAR_CONFIRM_TIME));
+        assert_eq!(clear_button_text(high_scores), "Click again to clear");
+        let text = table_text(high_scores, &BallTemplates::empty(), &ActionMap::default());
+        assert!(text.ends_with("Press C or click the button again to clear high scores"));
+    }
+
+    #[test]
+    fn clicking_the_button_twice_clears_the_scores() {
+        let mut world = clear_world();
+        world.spawn((Interaction::Pressed, ClearHighScoresButton));
+        world.run_system_once(clear_high_scores_input);
+        assert_eq!(world.resource::<HighScores>().scores.len(), 1);
+        world.run_system_once(clear_high_scores_input);
+        assert!(world.resource::<HighScores>().scores.is_empty());
+    }
+
+    #[test]
+    fn dates_are_formatted_in_utc() {
+        assert_eq!(date_text(0), "1970-01-01");
+        assert_eq!(date_text(951_782_400), "2000-02-29");
+        assert_eq!(date_text(1_792_281_600), "2026-10-18");
+    }
+}
@@ -0,0 +1,52 @@
+use crate::balls;
+use crate::danger;
+use crate::points;
+use crate::popups;
+use crate::settings::Settings;
+use crate::setup::NewGameEvent;
+use crate::state::{self, GameState};
+use crate::widgets;
+use bevy::prelude::*;
+
+/// Text shown over the game: points, score popups, upcoming balls, the danger line and its
+/// countdown, the screen of each game state, and the sliders and buttons of the menus
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Startup,
+            (
+                points::spawn_points_ui,
+                balls::spawn_queue_ui,
+                danger::spawn_danger_ui,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                points::update_points.run_if(resource_changed::<points::GamePoints>()),
+                balls::update_queue_ui.run_if(
+                    resource_changed::<balls::BallQueue>().or_else(resource_changed::<Settings>()),
+                ),
+                popups::despawn_popups
+                    .run_if(on_event::<NewGameEvent>())
+                    .before(popups::spawn_popups),
+                popups::spawn_popups.after(points::apply_points),
+                popups::update_popups,
+                danger::show_danger.after(danger::danger_check),
+                widgets::drag_sliders,
+                widgets::update_slider_fill.after(widgets::drag_sliders),
+                widgets::highlight_buttons,
+            ),
+        )
+        .add_systems(OnEnter(GameState::Loading), state::spawn_loading_screen)
+        .add_systems(OnExit(GameState::Loading), state::despawn_screen)
+        .add_systems(OnEnter(GameState::Menu), state::spawn_menu_screen)
+        .add_systems(OnExit(GameState::Menu), state::despawn_screen)
+        .add_systems(OnEnter(GameState::Paused), state::spawn_pause_screen)
+        .add_systems(OnExit(GameState::Paused), state::despawn_screen)
+        .add_systems(OnEnter(GameState::GameOver), state::spawn_game_over_screen)
+        .add_systems(OnExit(GameState::GameOver), state::despawn_screen);
+    }
+}
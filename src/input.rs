@@ -1,19 +1,123 @@
+use crate::actions::{Action, ActionInput, ActionMap, Binding};
+use crate::balls;
+use crate::highscores;
+use crate::replay::ReplayPlayer;
 use crate::setup::BoxSize;
+use crate::state::{self, GameState};
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use std::collections::BTreeMap;
+use std::f32::consts::PI;
+
+/// Box units per second the drop point moves when aiming with keys or a stick
+const AIM_SPEED: f32 = 6.0;
+
+/// Fraction of the window per second the camera orbits with keys or a stick, as if dragged
+const ORBIT_SPEED: f32 = 0.25;
+
+/// Zoom factor of one line of the mouse wheel
+const WHEEL_ZOOM: f32 = 0.9;
+
+/// Pixels scrolled by touchpads that count as one line of the mouse wheel
+const WHEEL_LINE_PIXELS: f32 = 100.0;
+
+/// Zoom speed when dragging with the zoom action, per window height dragged
+const ZOOM_DRAG_SPEED: f32 = 2.0;
+
+/// Zoom speed when aiming while holding a key or button of the zoom action, per second
+const ZOOM_SPEED: f32 = 1.5;
+
+/// Mouse, touch, keyboard and gamepad input of the player: dropping and holding balls, orbiting
+/// the camera, and moving between game states
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CursorTracking::new())
+            .init_resource::<KeyAim>()
+            .add_event::<OrbitUpdate>()
+            .add_event::<ZoomUpdate>()
+            .add_event::<BallSpawnUpdate>()
+            .add_systems(Update, (cursor_read, key_aim_read.after(cursor_read)))
+            .add_systems(OnEnter(GameState::Playing), clear_spawn_updates)
+            .add_systems(
+                Update,
+                (
+                    balls::hold_check.before(balls::insertion_check),
+                    balls::insertion_check.run_if(on_event::<BallSpawnUpdate>()),
+                )
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(not(resource_exists::<ReplayPlayer>())),
+            )
+            .add_systems(
+                Update,
+                (
+                    state::start_round_input,
+                    highscores::clear_high_scores_input,
+                )
+                    .run_if(in_state(GameState::Menu).or_else(in_state(GameState::GameOver))),
+            )
+            .add_systems(
+                Update,
+                state::pause_input
+                    .run_if(in_state(GameState::Playing).or_else(in_state(GameState::Paused))),
+            )
+            .insert_resource(GizmoConfig {
+                line_width: 100.0,
+                line_perspective: true,
+                depth_bias: 0.,
+                ..default()
+            });
+    }
+}
+
+/// Drop the click or key press that started or resumed the round, so it doesn't also drop a ball
+fn clear_spawn_updates(mut spawn_updates: ResMut<Events<BallSpawnUpdate>>) {
+    spawn_updates.clear();
+}
+
+/// What dragging a pointer does, decided when it is pressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DragRole {
+    Drop,
+    Orbit,
+    Zoom,
+    /// The pointer is not bound to an action where it was pressed
+    Ignored,
+}
+
+impl DragRole {
+    /// Pointers pressed over the box drop balls if they can, and orbit or zoom otherwise
+    /// Pointers pressed over a button or slider are left to it
+    fn new(over_ui: bool, on_box: bool, drops: bool, orbits: bool, zooms: bool) -> Self {
+        if over_ui {
+            DragRole::Ignored
+        } else if on_box && drops {
+            DragRole::Drop
+        } else if orbits {
+            DragRole::Orbit
+        } else if zooms {
+            DragRole::Zoom
+        } else {
+            DragRole::Ignored
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct TouchState {
     movement: Vec2,
     event_type: CursorChangeType,
     position: Vec2,
-    is_orbit: bool,
+    role: DragRole,
 }
 
-#[derive(Clone, Debug, Resource)]
+#[derive(Clone, Debug, Default, Resource)]
 pub struct CursorTracking {
     touches: BTreeMap<u64, TouchState>,
+    /// Mouse button of the last drag
+    mouse_button: Option<MouseButton>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -30,6 +134,12 @@ pub struct OrbitUpdate {
     pub delta: Vec2,
 }
 
+/// Multiplies the distance of the camera, zooming out above 1 and in below 1
+#[derive(Clone, Copy, Debug, PartialEq, Event)]
+pub struct ZoomUpdate {
+    pub factor: f32,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Event)]
 pub struct BallSpawnUpdate {
     pub cursor_type: CursorChangeType,
@@ -40,6 +150,7 @@ impl CursorTracking {
     pub fn new() -> Self {
         Self {
             touches: BTreeMap::new(),
+            mouse_button: None,
         }
     }
     fn get_tracked(
@@ -47,26 +158,44 @@ impl CursorTracking {
         mut touch_event: EventReader<TouchInput>,
         buttons: &Res<Input<MouseButton>>,
         window: &Query<&Window, With<PrimaryWindow>>,
-        is_orbit_fn: impl Fn(Vec2) -> Option<Vec3>,
+        map: &ActionMap,
+        over_ui: bool,
+        raycast_fn: impl Fn(Vec2) -> Option<Vec3>,
     ) {
         use bevy::input::touch::TouchPhase;
         // Cursor movement
         let cursor: Option<Vec2> = window.get_single().unwrap().cursor_position();
+        let drop_buttons: Vec<MouseButton> = map.mouse_buttons(Action::Drop).collect();
+        let orbit_buttons: Vec<MouseButton> = map.mouse_buttons(Action::Orbit).collect();
+        let zoom_buttons: Vec<MouseButton> = map.mouse_buttons(Action::Zoom).collect();
+        let pressed_button = buttons.get_just_pressed().find(|button| {
+            drop_buttons.contains(button)
+                || orbit_buttons.contains(button)
+                || zoom_buttons.contains(button)
+        });
         let mut new_touches = BTreeMap::new();
-        if buttons.just_pressed(MouseButton::Left) && cursor.is_some() {
+        if let Some((cursor, button)) = cursor.zip(pressed_button) {
+            self.mouse_button = Some(*button);
             new_touches.insert(
                 0,
                 TouchState {
                     event_type: CursorChangeType::DragStart,
-                    position: cursor.unwrap(),
-                    is_orbit: is_orbit_fn(cursor.unwrap()).is_none(),
+                    position: cursor,
+                    role: DragRole::new(
+                        over_ui,
+                        raycast_fn(cursor).is_some(),
+                        drop_buttons.contains(button),
+                        orbit_buttons.contains(button),
+                        zoom_buttons.contains(button),
+                    ),
                     movement: Vec2::ZERO,
                 },
             );
         } else {
-            let event_type = if buttons.just_released(MouseButton::Left) {
+            let button = self.mouse_button;
+            let event_type = if button.is_some_and(|button| buttons.just_released(button)) {
                 CursorChangeType::DragEnd
-            } else if buttons.pressed(MouseButton::Left) {
+            } else if button.is_some_and(|button| buttons.pressed(button)) {
                 CursorChangeType::Drag
             } else {
                 CursorChangeType::Move
@@ -93,7 +222,13 @@ impl CursorTracking {
                             movement: Vec2::ZERO,
                             event_type: CursorChangeType::DragStart,
                             position: touch.position,
-                            is_orbit: is_orbit_fn(touch.position).is_none(),
+                            role: DragRole::new(
+                                over_ui,
+                                raycast_fn(touch.position).is_some(),
+                                map.is_bound(Action::Drop, Binding::Touch),
+                                map.is_bound(Action::Orbit, Binding::Touch),
+                                map.is_bound(Action::Zoom, Binding::Touch),
+                            ),
                         },
                     );
                 }
@@ -131,7 +266,7 @@ impl CursorTracking {
             if touch.event_type == CursorChangeType::DragEnd {
                 continue;
             }
-            if new_touches.get(&id).is_none() {
+            if !new_touches.contains_key(id) {
                 new_touches.insert(
                     *id,
                     TouchState {
@@ -144,6 +279,36 @@ impl CursorTracking {
         }
         self.touches = new_touches;
     }
+    /// Turn two or more pressed pointers into a pinch, returning the zoom factor and the rotation
+    /// in radians of the first two since the last frame
+    /// The pointers of a pinch no longer drop, orbit or zoom on their own
+    fn pinch(&mut self) -> Option<(f32, f32)> {
+        let mut pressed: Vec<&mut TouchState> = self
+            .touches
+            .values_mut()
+            .filter(|touch| {
+                matches!(
+                    touch.event_type,
+                    CursorChangeType::DragStart
+                        | CursorChangeType::Drag
+                        | CursorChangeType::NoChange
+                )
+            })
+            .collect();
+        if pressed.len() < 2 {
+            return None;
+        }
+        for touch in pressed.iter_mut() {
+            touch.role = DragRole::Ignored;
+        }
+        let (a, b) = (&pressed[0], &pressed[1]);
+        let now = b.position - a.position;
+        let before = (b.position - b.movement) - (a.position - a.movement);
+        if now.length() < 1.0 || before.length() < 1.0 {
+            return None;
+        }
+        Some((before.length() / now.length(), before.angle_between(now)))
+    }
 }
 
 fn raycast_box_top(
@@ -166,29 +331,99 @@ fn raycast_box_top(
     }
 }
 
+/// Drop point aimed with the keyboard or a gamepad
+/// Aiming with keys or a stick takes over from the cursor until the mouse or a touch is used again
+#[derive(Resource, Default)]
+pub struct KeyAim {
+    /// Position of the drop point on the box top
+    position: Vec2,
+    active: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn cursor_read(
     touch_event: EventReader<TouchInput>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    mut key_aim: ResMut<KeyAim>,
+    mut wheel: EventReader<MouseWheel>,
     mut orbit_updates: EventWriter<OrbitUpdate>,
+    mut zoom_updates: EventWriter<ZoomUpdate>,
     mut ball_spawn_updates: EventWriter<BallSpawnUpdate>,
     buttons: Res<Input<MouseButton>>,
     window: Query<&Window, With<PrimaryWindow>>,
     cam_query: Query<(&Camera, &GlobalTransform)>,
     box_size: Res<BoxSize>,
+    actions: ActionInput,
     mut cursor_state: ResMut<CursorTracking>,
+    ui: Query<&Interaction>,
 ) {
     let raycast_fn = |cursor| raycast_box_top(cursor, cam_query.single(), &box_size);
-    cursor_state.get_tracked(touch_event, &buttons, &window, raycast_fn);
-    // Orbit events
+    let pointer_used = cursor_moved.read().count() > 0
+        || buttons.get_just_pressed().next().is_some()
+        || !touch_event.is_empty();
+    if pointer_used {
+        key_aim.active = false;
+    }
+    // Pointers over the buttons and sliders of the menus don't move the camera behind them
+    let over_ui = ui
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    cursor_state.get_tracked(
+        touch_event,
+        &buttons,
+        &window,
+        actions.map(),
+        over_ui,
+        raycast_fn,
+    );
+    let window_size = window.get_single().map_or(Vec2::ONE, |window| {
+        Vec2::new(window.width(), window.height())
+    });
+    // Zoom events
+    let lines: f32 = wheel
+        .read()
+        .map(|scroll| match scroll.unit {
+            MouseScrollUnit::Line => scroll.y,
+            MouseScrollUnit::Pixel => scroll.y / WHEEL_LINE_PIXELS,
+        })
+        .sum();
+    if lines != 0.0 && !over_ui {
+        zoom_updates.send(ZoomUpdate {
+            factor: WHEEL_ZOOM.powf(lines),
+        });
+    }
+    if let Some((zoom, rotation)) = cursor_state.pinch() {
+        zoom_updates.send(ZoomUpdate { factor: zoom });
+        // Twisting clockwise turns the front of the box left, as if dragged left
+        orbit_updates.send(OrbitUpdate {
+            delta: Vec2::new(-rotation / (PI * 2.0) * window_size.x, 0.0),
+        });
+    }
+    // Orbit and zoom drag events, also sent for pointers held still so they stop the orbit
     for (_id, change) in cursor_state.touches.iter() {
-        if change.event_type == CursorChangeType::Drag && change.is_orbit {
-            orbit_updates.send(OrbitUpdate {
+        if !matches!(
+            change.event_type,
+            CursorChangeType::Drag | CursorChangeType::NoChange
+        ) {
+            continue;
+        }
+        match change.role {
+            DragRole::Orbit => orbit_updates.send(OrbitUpdate {
                 delta: change.movement,
-            });
+            }),
+            // Dragging down zooms out
+            DragRole::Zoom => zoom_updates.send(ZoomUpdate {
+                factor: (change.movement.y / window_size.y * ZOOM_DRAG_SPEED).exp(),
+            }),
+            DragRole::Drop | DragRole::Ignored => {}
         }
     }
-    // Ball spawning events
+    // Ball spawning events, unless aiming with keys
+    if key_aim.active {
+        return;
+    }
     let ball_spawner = cursor_state.touches.iter().find_map(|(_id, touch)| {
-        if touch.is_orbit && touch.event_type != CursorChangeType::Move {
+        if touch.role != DragRole::Drop && touch.event_type != CursorChangeType::Move {
             None
         } else {
             raycast_fn(touch.position).map(|x| (Some(x), touch.event_type))
@@ -200,3 +435,94 @@ pub fn cursor_read(
         position,
     });
 }
+
+/// Aim with the keys of the aim actions or the left stick, drop with the keys and buttons of the
+/// drop action, orbit with the keys of the orbit left and right actions, the right stick, or by
+/// aiming while holding a key or button of the orbit action, and zoom by aiming up and down while holding a key or button of the zoom action
+/// Aiming moves the drop point relative to the camera, so up always moves away from it
+#[allow(clippy::too_many_arguments)]
+pub fn key_aim_read(
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    actions: ActionInput,
+    time: Res<Time>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<&GlobalTransform, With<Camera>>,
+    box_size: Res<BoxSize>,
+    mut key_aim: ResMut<KeyAim>,
+    mut orbit_updates: EventWriter<OrbitUpdate>,
+    mut zoom_updates: EventWriter<ZoomUpdate>,
+    mut ball_spawn_updates: EventWriter<BallSpawnUpdate>,
+) {
+    let mut aim = Vec2::new(
+        actions.key_axis(Action::AimLeft, Action::AimRight),
+        actions.key_axis(Action::AimDown, Action::AimUp),
+    );
+    let mut orbit = Vec2::new(actions.key_axis(Action::OrbitLeft, Action::OrbitRight), 0.0);
+    let drop = actions.key_just_pressed(Action::Drop);
+    for gamepad in gamepads.iter() {
+        let axis = |axis_type| {
+            axes.get(GamepadAxis::new(gamepad, axis_type))
+                .unwrap_or(0.0)
+        };
+        aim += Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        // Pushing the stick up tilts the camera like dragging up
+        orbit += Vec2::new(
+            axis(GamepadAxisType::RightStickX),
+            -axis(GamepadAxisType::RightStickY),
+        );
+    }
+    let mut aim = aim.clamp_length_max(1.0);
+    if actions.key_pressed(Action::Orbit) {
+        orbit += Vec2::new(aim.x, -aim.y);
+        aim = Vec2::ZERO;
+    } else if actions.key_pressed(Action::Zoom) {
+        // Aiming up zooms in
+        if aim.y != 0.0 {
+            zoom_updates.send(ZoomUpdate {
+                factor: (-aim.y * ZOOM_SPEED * time.delta_seconds()).exp(),
+            });
+        }
+        aim = Vec2::ZERO;
+    }
+    if aim != Vec2::ZERO || drop {
+        key_aim.active = true;
+    }
+    if orbit != Vec2::ZERO {
+        if let Ok(window) = window.get_single() {
+            let size = Vec2::new(window.width(), window.height());
+            orbit_updates.send(OrbitUpdate {
+                delta: orbit * size * ORBIT_SPEED * time.delta_seconds(),
+            });
+        }
+    }
+    if !key_aim.active {
+        return;
+    }
+    if let Ok(camera) = camera.get_single() {
+        // Directions of the camera flattened onto the box top
+        let right = (camera.right() * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+        let away = Vec3::Y.cross(right);
+        let movement = (right * aim.x + away * aim.y) * AIM_SPEED * time.delta_seconds();
+        key_aim.position += Vec2::new(movement.x, movement.z);
+    }
+    key_aim.position = key_aim.position.clamp(
+        Vec2::new(-box_size.x, -box_size.z),
+        Vec2::new(box_size.x, box_size.z),
+    );
+    ball_spawn_updates.send(BallSpawnUpdate {
+        cursor_type: if drop {
+            CursorChangeType::DragEnd
+        } else {
+            CursorChangeType::Move
+        },
+        position: Some(Vec3::new(
+            key_aim.position.x,
+            box_size.y,
+            key_aim.position.y,
+        )),
+    });
+}
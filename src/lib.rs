@@ -0,0 +1,108 @@
+pub mod actions;
+pub mod balls;
+pub mod camera;
+pub mod cli;
+pub mod danger;
+pub mod headless;
+pub mod highscores;
+pub mod hud;
+pub mod input;
+pub mod points;
+pub mod popups;
+pub mod replay;
+pub mod rng;
+pub mod savegame;
+pub mod scene_scale;
+pub mod settings;
+pub mod setup;
+pub mod sound;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod widgets;
+pub mod window_resize;
+
+use bevy::pbr::DirectionalLightShadowMap;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use std::path::PathBuf;
+
+/// Settings of the game that an app embedding it can change
+#[derive(Resource, Clone, Debug)]
+pub struct BallBlitzConfig {
+    pub gravity: Vec3,
+    pub clear_color: Color,
+    pub shadow_map_size: usize,
+    /// Half size of the box at the start of a round
+    pub box_size: setup::BoxSize,
+    /// Leave out the input, camera, UI, sound, settings, high scores and saves, for running the
+    /// game without a window
+    pub headless: bool,
+    /// Seed of every round, to play the same sequence of balls again, or a new seed each round
+    pub seed: Option<u64>,
+    /// File the drops of each round are saved to when the round ends
+    pub record: Option<PathBuf>,
+    /// Replay file whose drops are played instead of the player's
+    pub replay: Option<PathBuf>,
+}
+
+impl Default for BallBlitzConfig {
+    fn default() -> Self {
+        Self {
+            gravity: Vec3::new(0.0, -45.0, 0.0),
+            clear_color: Color::rgb(0.4, 0.4, 0.4),
+            shadow_map_size: 4096,
+            box_size: setup::BoxSize::default(),
+            headless: false,
+            seed: None,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+/// The whole game, made of the plugins of each module
+/// Physics comes from `PhysicsPlugins`, which must be added to the app before this plugin
+#[derive(Default)]
+pub struct BallBlitzPlugin {
+    pub config: BallBlitzConfig,
+}
+
+impl Plugin for BallBlitzPlugin {
+    fn build(&self, app: &mut App) {
+        let config = &self.config;
+        if let Some(seed) = config.seed {
+            info!("Using seed {}", seed);
+        }
+        app.insert_resource(config.clone())
+            .insert_resource(Gravity(config.gravity))
+            .insert_resource(ClearColor(config.clear_color))
+            .insert_resource(DirectionalLightShadowMap {
+                size: config.shadow_map_size,
+            })
+            .insert_resource(rng::GameRng::new(config.seed))
+            .add_plugins((
+                balls::BallsPlugin,
+                points::PointsPlugin,
+                setup::ArenaPlugin,
+                state::StatePlugin,
+                replay::ReplayPlugin {
+                    record: config.record.clone(),
+                    replay: config.replay.clone(),
+                },
+                danger::DangerPlugin,
+            ));
+        if !config.headless {
+            app.add_plugins((
+                input::InputPlugin,
+                camera::CameraPlugin,
+                hud::HudPlugin,
+                window_resize::ResizePlugin,
+                highscores::HighScoresPlugin,
+                savegame::SaveGamePlugin,
+                settings::SettingsPlugin,
+                sound::SoundPlugin,
+            ));
+        }
+    }
+}
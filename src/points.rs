@@ -1,6 +1,192 @@
-use crate::balls::BallSize;
+use crate::balls::{self, BallDropped, BallMerged, BallSize, BallTemplates, FinalBallsVanished};
+use crate::replay::PhysicsTick;
+use crate::setup::{BoxSize, NewGameEvent};
+use crate::state::GameState;
 use bevy::prelude::*;
 
+/// Points of the round, scored from drops, merges and lost balls by the scoring rules
+pub struct PointsPlugin;
+
+impl Plugin for PointsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GamePoints(0))
+            .init_resource::<ScoringRules>()
+            .init_resource::<ComboTracker>()
+            .add_event::<BallLost>()
+            .add_event::<PointsEvent>()
+            .add_systems(
+                Update,
+                ball_out_of_bounds.run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (score_drops, score_merges, score_lost, apply_points)
+                    .chain()
+                    .after(balls::merge_check)
+                    .after(ball_out_of_bounds)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, reset_combo.run_if(on_event::<NewGameEvent>()));
+    }
+}
+
+/// Rules turning drops, merges and lost balls into points
+/// Merge points and the final tier bonus come from the ball catalog
+#[derive(Resource, Clone, Debug)]
+pub struct ScoringRules {
+    /// Points for each size of a dropped ball
+    pub drop_per_size: i32,
+    /// Points lost for each size of a ball that falls out of the box
+    pub lost_per_size: i32,
+    /// Merges less than this many seconds apart continue a combo
+    pub combo_window: f32,
+    /// Added to the multiplier by each merge of a combo after the first one
+    pub combo_step: i32,
+    /// Largest multiplier of a combo
+    pub max_combo_multiplier: i32,
+}
+
+impl Default for ScoringRules {
+    fn default() -> Self {
+        Self {
+            drop_per_size: 1,
+            lost_per_size: 100,
+            combo_window: 1.0,
+            combo_step: 1,
+            max_combo_multiplier: 5,
+        }
+    }
+}
+
+impl ScoringRules {
+    /// Multiplier of the nth merge of a combo, starting at 1
+    pub fn combo_multiplier(&self, combo: u32) -> i32 {
+        (1 + (combo as i32 - 1) * self.combo_step).min(self.max_combo_multiplier)
+    }
+}
+
+/// Where points came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointsSource {
+    Drop {
+        size: u8,
+    },
+    /// Merge creating a ball of this size, as the nth merge of a combo
+    Merge {
+        size: u8,
+        combo: u32,
+        multiplier: i32,
+    },
+    FinalBonus,
+    Lost {
+        size: u8,
+    },
+}
+
+/// Sent for every change of the points, applied to [`GamePoints`] in the same frame
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PointsEvent {
+    pub amount: i32,
+    pub source: PointsSource,
+    /// Where in the box the points were scored
+    pub position: Vec3,
+}
+
+/// Merges of the current combo
+/// Counted in physics steps rather than frames so replays score the same combos
+#[derive(Resource, Default)]
+pub struct ComboTracker {
+    combo: u32,
+    last_merge: Option<u32>,
+}
+
+impl ComboTracker {
+    /// Count a merge at a physics step and return its place in the combo
+    fn merge(&mut self, tick: u32, window: u32) -> u32 {
+        match self.last_merge {
+            Some(last) if tick - last <= window => self.combo += 1,
+            _ => self.combo = 1,
+        }
+        self.last_merge = Some(tick);
+        self.combo
+    }
+}
+
+fn reset_combo(mut combo: ResMut<ComboTracker>) {
+    *combo = ComboTracker::default();
+}
+
+/// Score the balls dropped into the box
+pub fn score_drops(
+    rules: Res<ScoringRules>,
+    mut dropped: EventReader<BallDropped>,
+    mut points: EventWriter<PointsEvent>,
+) {
+    for drop in dropped.read() {
+        points.send(PointsEvent {
+            amount: drop.size as i32 * rules.drop_per_size,
+            source: PointsSource::Drop { size: drop.size },
+            position: drop.position,
+        });
+    }
+}
+
+/// Score merges with the points of the catalog tier times the combo multiplier, and the final
+/// tier bonus
+pub fn score_merges(
+    rules: Res<ScoringRules>,
+    ball_templates: Res<BallTemplates>,
+    tick: Res<PhysicsTick>,
+    mut combo: ResMut<ComboTracker>,
+    mut merged: EventReader<BallMerged>,
+    mut vanished: EventReader<FinalBallsVanished>,
+    mut points: EventWriter<PointsEvent>,
+) {
+    let window = (rules.combo_window as f64 * crate::replay::TICK_HZ) as u32;
+    for merge in merged.read() {
+        let combo = combo.merge(tick.get(), window);
+        let multiplier = rules.combo_multiplier(combo);
+        points.send(PointsEvent {
+            amount: ball_templates.tier(merge.size - 1).points * multiplier,
+            source: PointsSource::Merge {
+                size: merge.size,
+                combo,
+                multiplier,
+            },
+            position: merge.position,
+        });
+    }
+    for vanish in vanished.read() {
+        points.send(PointsEvent {
+            amount: ball_templates.final_bonus(),
+            source: PointsSource::FinalBonus,
+            position: vanish.position,
+        });
+    }
+}
+
+/// Score the balls that fell out of the box
+pub fn score_lost(
+    rules: Res<ScoringRules>,
+    mut lost: EventReader<BallLost>,
+    mut points: EventWriter<PointsEvent>,
+) {
+    for ball in lost.read() {
+        points.send(PointsEvent {
+            amount: -(ball.size as i32) * rules.lost_per_size,
+            source: PointsSource::Lost { size: ball.size },
+            position: ball.position,
+        });
+    }
+}
+
+/// Add the points of this frame to the total
+pub fn apply_points(mut events: EventReader<PointsEvent>, mut points: ResMut<GamePoints>) {
+    for event in events.read() {
+        points.0 += event.amount;
+    }
+}
+
 /// Resource that keeps track of points in game
 #[derive(Resource)]
 pub struct GamePoints(pub i32);
@@ -31,16 +217,29 @@ pub fn update_points(mut query: Query<&mut Text, With<PointsDisplay>>, points: R
     text.sections[1].value = format!("{}", points.0);
 }
 
-/// Despawns balls out of bounds and reduces point count
+/// Sent when a ball falls out of the box and is despawned
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BallLost {
+    pub size: u8,
+    pub position: Vec3,
+}
+
+/// Despawns balls out of bounds
+/// The position of the lost ball is taken at the height of the floor, below the side of the box
+/// where it fell out
 pub fn ball_out_of_bounds(
-    mut points: ResMut<GamePoints>,
     query: Query<(Entity, &Transform, &BallSize)>,
+    box_size: Res<BoxSize>,
     mut commands: Commands,
+    mut lost: EventWriter<BallLost>,
 ) {
     for (ent, trans, size) in query.iter() {
         if trans.translation.y < -50. {
             commands.entity(ent).despawn_recursive();
-            points.as_mut().0 -= (size.0 as i32) * 100;
+            lost.send(BallLost {
+                size: size.0,
+                position: Vec3::new(trans.translation.x, -box_size.y, trans.translation.z),
+            });
         }
     }
 }
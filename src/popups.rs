@@ -0,0 +1,104 @@
+use crate::points::{PointsEvent, PointsSource};
+use bevy::prelude::*;
+
+/// Seconds a popup stays on screen
+const POPUP_LIFETIME: f32 = 1.2;
+
+/// World units a popup rises per second
+const POPUP_RISE: f32 = 1.5;
+
+/// Text showing points scored at a point in the box
+#[derive(Component)]
+pub struct ScorePopup {
+    /// Where the points were scored
+    anchor: Vec3,
+    age: f32,
+}
+
+/// Spawn a popup for the points of every merge and lost ball
+pub fn spawn_popups(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut events: EventReader<PointsEvent>,
+) {
+    for event in events.read() {
+        let text = match event.source {
+            // Drops score on every click, so they would only clutter the screen
+            PointsSource::Drop { .. } => continue,
+            PointsSource::Merge { multiplier, .. } if multiplier > 1 => {
+                format!("{:+} x{}", event.amount, multiplier)
+            }
+            _ => format!("{:+}", event.amount),
+        };
+        let color = if event.amount >= 0 {
+            Color::rgb(0.6, 1.0, 0.6)
+        } else {
+            Color::rgb(1.0, 0.4, 0.4)
+        };
+        commands.spawn((
+            TextBundle::from_section(
+                text,
+                TextStyle {
+                    font: asset_server.load("fonts/mononoki-Regular.ttf"),
+                    font_size: 30.0,
+                    color,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            }),
+            ScorePopup {
+                anchor: event.position,
+                age: 0.0,
+            },
+        ));
+    }
+}
+
+/// Rise and fade popups, keeping them over their point in the box as the camera orbits
+pub fn update_popups(
+    mut commands: Commands,
+    time: Res<Time>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    mut popups: Query<(
+        Entity,
+        &mut ScorePopup,
+        &mut Style,
+        &mut Text,
+        &mut Visibility,
+        &Node,
+    )>,
+) {
+    let Ok((camera, camera_transform)) = camera.get_single() else {
+        return;
+    };
+    for (ent, mut popup, mut style, mut text, mut visibility, node) in popups.iter_mut() {
+        popup.age += time.delta_seconds();
+        if popup.age > POPUP_LIFETIME {
+            commands.entity(ent).despawn_recursive();
+            continue;
+        }
+        let position = popup.anchor + Vec3::Y * POPUP_RISE * popup.age;
+        let Some(viewport) = camera.world_to_viewport(camera_transform, position) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Inherited;
+        // Center the text on the point
+        let size = node.size();
+        style.left = Val::Px(viewport.x - size.x / 2.0);
+        style.top = Val::Px(viewport.y - size.y / 2.0);
+        let alpha = 1.0 - popup.age / POPUP_LIFETIME;
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(alpha);
+        }
+    }
+}
+
+/// Remove the popups of the previous game
+pub fn despawn_popups(mut commands: Commands, popups: Query<Entity, With<ScorePopup>>) {
+    for ent in popups.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
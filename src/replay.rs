@@ -0,0 +1,277 @@
+use crate::balls::{self, BallDropped, BallTemplates};
+use crate::points::GamePoints;
+use crate::rng::GameRng;
+use crate::setup::{self, NewGameEvent};
+use crate::state::GameState;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use bevy_xpbd_3d::{PhysicsSchedule, PhysicsStepSet};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Physics steps per second, the default rate of the physics engine
+/// Replays run exactly one step of this length per frame
+pub const TICK_HZ: f64 = 60.0;
+
+/// Every drop of a round along with the seed of the round
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Replay {
+    pub seed: u64,
+    pub drops: Vec<ReplayDrop>,
+    /// Points at the end of the recorded round, to compare with the replayed round
+    pub final_points: Option<i32>,
+}
+
+/// A ball dropped at a physics step of the round
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ReplayDrop {
+    /// Number of physics steps since the start of the round
+    pub tick: u32,
+    pub size: u8,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl Replay {
+    /// Read a replay from a RON file
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        ron::de::from_str(&text).map_err(|err| err.to_string())
+    }
+    /// Write the replay to a RON file
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        std::fs::write(path, text).map_err(|err| err.to_string())
+    }
+}
+
+/// Number of physics steps run since launch
+/// A frame can run zero, one or several steps, so drops are timed in steps rather than frames
+#[derive(Resource, Default)]
+pub struct PhysicsTick(u32);
+
+impl PhysicsTick {
+    /// Number of physics steps run so far
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+fn count_physics_step(mut tick: ResMut<PhysicsTick>) {
+    tick.0 += 1;
+}
+
+/// Records the drops of the current round, and saves them at the end of the round if a record
+/// path was given
+/// Resumed rounds are not recorded, since their replay would start from an empty box
+#[derive(Resource, Default)]
+pub struct ReplayRecorder {
+    path: Option<PathBuf>,
+    /// False until a new round starts
+    recording: bool,
+    replay: Replay,
+    round_start: u32,
+}
+
+/// Drops a recorded round again, present when a replay was given
+#[derive(Resource)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    next: usize,
+    round_start: u32,
+}
+
+/// Records every round and plays back a replay file
+pub struct ReplayPlugin {
+    /// File the drops of each round are saved to
+    pub record: Option<PathBuf>,
+    /// Replay file to watch again
+    pub replay: Option<PathBuf>,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if self.record.is_some() {
+            // Merges are checked once per frame, so the recording needs one physics step per frame
+            // like playback for the replay to merge the same balls
+            app.insert_resource(Time::new_with(Physics::fixed_once_hz(TICK_HZ)));
+        }
+        app.init_resource::<PhysicsTick>()
+            .add_systems(
+                PhysicsSchedule,
+                count_physics_step.before(PhysicsStepSet::BroadPhase),
+            )
+            .insert_resource(ReplayRecorder {
+                path: self.record.clone(),
+                ..default()
+            })
+            .add_systems(
+                Update,
+                (start_recording, start_playback)
+                    .after(setup::new_game)
+                    .run_if(on_event::<NewGameEvent>()),
+            )
+            .add_systems(Update, record_drops.run_if(on_event::<BallDropped>()))
+            .add_systems(
+                Update,
+                play_drops
+                    .before(balls::merge_check)
+                    .run_if(resource_exists::<ReplayPlayer>())
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnEnter(GameState::GameOver), finish_round)
+            .add_systems(
+                OnEnter(GameState::Menu),
+                start_replayed_game.run_if(resource_exists::<ReplayPlayer>()),
+            );
+        let Some(path) = &self.replay else {
+            return;
+        };
+        match Replay::load(path) {
+            Ok(replay) => {
+                info!(
+                    "Replaying {} drops from {}",
+                    replay.drops.len(),
+                    path.display()
+                );
+                // Same seed for the same queue of balls, and one physics step per frame so drops
+                // land on the step they were recorded at
+                app.insert_resource(GameRng::new(Some(replay.seed)))
+                    .insert_resource(Time::new_with(Physics::fixed_once_hz(TICK_HZ)))
+                    .insert_resource(ReplayPlayer {
+                        replay,
+                        next: 0,
+                        round_start: 0,
+                    });
+            }
+            Err(err) => error!("Could not load replay {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// Start recording the drops of a new round
+fn start_recording(
+    mut recorder: ResMut<ReplayRecorder>,
+    rng: Res<GameRng>,
+    tick: Res<PhysicsTick>,
+) {
+    recorder.replay = Replay {
+        seed: rng.seed(),
+        ..default()
+    };
+    recorder.round_start = tick.0;
+    recorder.recording = true;
+}
+
+/// Start dropping the replayed balls from the beginning
+fn start_playback(player: Option<ResMut<ReplayPlayer>>, tick: Res<PhysicsTick>) {
+    if let Some(mut player) = player {
+        player.next = 0;
+        player.round_start = tick.0;
+    }
+}
+
+/// Add the drops of this frame to the recording
+fn record_drops(
+    mut recorder: ResMut<ReplayRecorder>,
+    mut dropped: EventReader<BallDropped>,
+    tick: Res<PhysicsTick>,
+) {
+    let tick = tick.0 - recorder.round_start;
+    for drop in dropped.read() {
+        recorder.replay.drops.push(ReplayDrop {
+            tick,
+            size: drop.size,
+            position: drop.position.to_array(),
+            rotation: drop.rotation.to_array(),
+        });
+    }
+}
+
+/// Drop every replayed ball whose tick has been reached, through the same path as the player
+fn play_drops(
+    mut player: ResMut<ReplayPlayer>,
+    tick: Res<PhysicsTick>,
+    ball_templates: Res<BallTemplates>,
+    mut commands: Commands,
+    mut dropped: EventWriter<BallDropped>,
+) {
+    let tick = tick.0 - player.round_start;
+    while let Some(drop) = player.replay.drops.get(player.next).copied() {
+        if drop.tick > tick {
+            break;
+        }
+        player.next += 1;
+        balls::drop_ball(
+            BallDropped {
+                size: drop.size,
+                position: Vec3::from_array(drop.position),
+                rotation: Quat::from_array(drop.rotation),
+            },
+            &ball_templates,
+            &mut commands,
+            &mut dropped,
+        );
+    }
+}
+
+/// Save the recorded round, and compare a replayed round with its recording
+fn finish_round(
+    mut recorder: ResMut<ReplayRecorder>,
+    player: Option<Res<ReplayPlayer>>,
+    points: Res<GamePoints>,
+) {
+    if let Some(player) = player {
+        match player.replay.final_points {
+            Some(expected) if expected != points.0 => warn!(
+                "Replay ended with {} points but the recorded round ended with {}",
+                points.0, expected
+            ),
+            _ => info!("Replay ended with {} points", points.0),
+        }
+    }
+    recorder.replay.final_points = Some(points.0);
+    let Some(path) = recorder.path.clone() else {
+        return;
+    };
+    if !recorder.recording {
+        warn!("The resumed round was not recorded to {}", path.display());
+        return;
+    }
+    match recorder.replay.save(&path) {
+        Ok(()) => info!("Saved replay to {}", path.display()),
+        Err(err) => error!("Could not save replay {}: {}", path.display(), err),
+    }
+}
+
+/// Skip the title screen when watching a replay
+fn start_replayed_game(mut new_game: EventWriter<NewGameEvent>) {
+    new_game.send(NewGameEvent);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_round_trips_through_ron() {
+        let replay = Replay {
+            seed: 42,
+            drops: vec![ReplayDrop {
+                tick: 90,
+                size: 3,
+                position: [1.0, 6.0, -2.5],
+                rotation: Quat::from_rotation_y(0.3).to_array(),
+            }],
+            final_points: Some(12),
+        };
+        let text = ron::ser::to_string(&replay).unwrap();
+        let loaded: Replay = ron::de::from_str(&text).unwrap();
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.drops[0].tick, 90);
+        assert_eq!(loaded.drops[0].position, replay.drops[0].position);
+        assert_eq!(loaded.drops[0].rotation, replay.drops[0].rotation);
+        assert_eq!(loaded.final_points, Some(12));
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+/// Source of every random draw of a round, so a round can be replayed from its seed
+#[derive(Resource)]
+pub struct GameRng {
+    /// Seed chosen at launch, reused by every round
+    fixed_seed: Option<u64>,
+    seed: u64,
+    /// The generator of `StdRng`, which can also tell and set its position in the sequence
+    rng: ChaCha12Rng,
+}
+
+impl GameRng {
+    /// Create the generator, with a fixed seed or a random seed for each round
+    pub fn new(fixed_seed: Option<u64>) -> Self {
+        let seed = fixed_seed.unwrap_or_else(rand::random);
+        Self {
+            fixed_seed,
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+        }
+    }
+    /// Start the sequence of a new round, from the fixed seed if there is one
+    pub fn restart(&mut self) {
+        *self = Self::new(self.fixed_seed);
+    }
+    /// Continue a saved round of the given seed from a position returned by [`Self::position`]
+    pub fn resume(&mut self, seed: u64, position: u64) {
+        self.seed = seed;
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+        self.rng.set_word_pos(position.into());
+    }
+    /// Number of random words drawn since the start of the round
+    pub fn position(&self) -> u64 {
+        self.rng.get_word_pos() as u64
+    }
+    /// Seed of the current round
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+
+    #[test]
+    fn seeds_give_the_sequence_of_std_rng() {
+        let mut rng = GameRng::new(Some(42));
+        let mut std_rng = StdRng::seed_from_u64(42);
+        for _ in 0..10 {
+            assert_eq!(rng.gen::<u64>(), std_rng.gen::<u64>());
+        }
+    }
+
+    #[test]
+    fn resumed_rounds_continue_the_sequence() {
+        let mut rng = GameRng::new(None);
+        for _ in 0..5 {
+            rng.gen_range(1..=4);
+        }
+        let mut resumed = GameRng::new(None);
+        resumed.resume(rng.seed(), rng.position());
+        assert_eq!(resumed.seed(), rng.seed());
+        for _ in 0..10 {
+            assert_eq!(resumed.gen_range(1..=4), rng.gen_range(1..=4));
+        }
+    }
+}
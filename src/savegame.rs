@@ -0,0 +1,318 @@
+use crate::balls::{self, BallQueue, BallSize, BallTemplates, ExampleBall};
+use crate::points::GamePoints;
+use crate::replay::ReplayPlayer;
+use crate::rng::GameRng;
+use crate::setup::{BoxSize, BoxTag, WallTag};
+use crate::state::GameState;
+use crate::stats::RoundStats;
+use crate::{storage, BallBlitzConfig};
+use bevy::prelude::*;
+use bevy::window::{WindowCloseRequested, WindowFocused};
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Version of the save format, increased whenever it changes
+/// Saves of any other version are ignored
+pub const SAVE_VERSION: u32 = 3;
+
+/// Name of the stored save file
+const SAVE_FILE: &str = "save.ron";
+
+/// Everything needed to continue a round after the game is closed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SaveGame {
+    pub version: u32,
+    pub seed: u64,
+    /// Position in the random sequence of the seed, so the round continues with the same balls
+    pub rng_position: u64,
+    pub points: i32,
+    pub box_size: [f32; 3],
+    pub balls: Vec<SavedBall>,
+    /// Size of the ball waiting to be dropped
+    pub example_ball: u8,
+    /// Rotation of the ball waiting to be dropped
+    pub example_rotation: [f32; 4],
+    pub upcoming: Vec<u8>,
+    pub held: Option<u8>,
+    pub can_hold: bool,
+    pub stats: RoundStats,
+}
+
+/// A ball in the box along with its motion
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SavedBall {
+    pub size: u8,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    /// Below 1 for balls still growing after a merge
+    pub scale: f32,
+    pub linear_velocity: [f32; 3],
+    pub angular_velocity: [f32; 3],
+}
+
+/// Only the version of a save, read before the rest so older formats are recognized
+#[derive(Deserialize)]
+struct SaveVersion {
+    version: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SaveGameError {
+    #[error("could not parse the save: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error(
+        "the save has version {found} but version {} is expected",
+        SAVE_VERSION
+    )]
+    Version { found: u32 },
+}
+
+impl SaveGame {
+    /// Parse a save, rejecting saves of another version
+    pub fn parse(text: &str) -> Result<Self, SaveGameError> {
+        let SaveVersion { version } = ron::from_str(text)?;
+        if version != SAVE_VERSION {
+            return Err(SaveGameError::Version { found: version });
+        }
+        Ok(ron::from_str(text)?)
+    }
+    /// Read the stored save, if there is a valid one
+    pub fn load() -> Option<Self> {
+        let text = storage::read(SAVE_FILE)?;
+        SaveGame::parse(&text)
+            .map_err(|err| warn!("Ignoring the saved game: {}", err))
+            .ok()
+    }
+    /// Store the save, replacing the previous one
+    pub fn store(&self) {
+        let result = ron::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|text| storage::write(SAVE_FILE, &text));
+        if let Err(err) = result {
+            warn!("Could not save the game: {}", err);
+        }
+    }
+    /// Delete the stored save
+    pub fn discard() {
+        if let Err(err) = storage::remove(SAVE_FILE) {
+            warn!("Could not delete the saved game: {}", err);
+        }
+    }
+}
+
+/// Saves the round when it is paused or the window is left, and resumes it on the next launch
+pub struct SaveGamePlugin;
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(GameState::Loading), resume_saved_game)
+            .add_systems(
+                OnEnter(GameState::Paused),
+                save_game.run_if(not(resource_exists::<ReplayPlayer>())),
+            )
+            .add_systems(
+                Update,
+                save_game
+                    .run_if(window_left)
+                    .run_if(
+                        in_state(GameState::Playing)
+                            .or_else(in_state(GameState::Paused))
+                            .or_else(in_state(GameState::Settings)),
+                    )
+                    .run_if(not(resource_exists::<ReplayPlayer>())),
+            )
+            .add_systems(OnEnter(GameState::GameOver), discard_save);
+    }
+}
+
+/// True when the window is being closed or loses focus, which is the last chance to save when a
+/// browser tab is closed
+fn window_left(
+    mut closed: EventReader<WindowCloseRequested>,
+    mut focused: EventReader<WindowFocused>,
+) -> bool {
+    let left = focused.read().any(|event| !event.focused);
+    closed.read().count() > 0 || left
+}
+
+/// Store the current round
+pub fn save_game(
+    balls: Query<(&BallSize, &Transform, &LinearVelocity, &AngularVelocity), Without<ExampleBall>>,
+    example_ball: Query<(&BallSize, &Transform), With<ExampleBall>>,
+    points: Res<GamePoints>,
+    box_size: Res<BoxSize>,
+    queue: Res<BallQueue>,
+    stats: Res<RoundStats>,
+    rng: Res<GameRng>,
+) {
+    let Ok((example_size, example_trans)) = example_ball.get_single() else {
+        return;
+    };
+    let balls = balls
+        .iter()
+        .map(|(size, trans, linear, angular)| SavedBall {
+            size: size.0,
+            translation: trans.translation.to_array(),
+            rotation: trans.rotation.to_array(),
+            scale: trans.scale.x,
+            linear_velocity: linear.0.to_array(),
+            angular_velocity: angular.0.to_array(),
+        })
+        .collect();
+    SaveGame {
+        version: SAVE_VERSION,
+        seed: rng.seed(),
+        rng_position: rng.position(),
+        points: points.0,
+        box_size: [box_size.x, box_size.y, box_size.z],
+        balls,
+        example_ball: example_size.0,
+        example_rotation: example_trans.rotation.to_array(),
+        upcoming: queue.upcoming().collect(),
+        held: queue.held(),
+        can_hold: queue.can_hold(),
+        stats: stats.clone(),
+    }
+    .store();
+}
+
+/// Delete the save once its round is over
+pub fn discard_save() {
+    SaveGame::discard();
+}
+
+/// Restore the stored round once loading is done and show it paused
+/// Nothing happens if there is no save
+#[allow(clippy::too_many_arguments)]
+pub fn resume_saved_game(
+    mut commands: Commands,
+    mut box_query: Query<&mut Transform, With<BoxTag>>,
+    mut walls: Query<(&WallTag, &mut Collider)>,
+    mut box_size: ResMut<BoxSize>,
+    mut points: ResMut<GamePoints>,
+    mut queue: ResMut<BallQueue>,
+    mut stats: ResMut<RoundStats>,
+    mut rng: ResMut<GameRng>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut animations: ResMut<Assets<AnimationClip>>,
+    ball_templates: Res<BallTemplates>,
+    config: Res<BallBlitzConfig>,
+) {
+    let Some(save) = SaveGame::load() else {
+        return;
+    };
+    // Saves made with another catalog may have sizes that no longer exist
+    let valid_size = |size: u8| (1..=ball_templates.final_tier()).contains(&size);
+    let mut sizes = save
+        .balls
+        .iter()
+        .map(|ball| ball.size)
+        .chain(save.upcoming.iter().copied())
+        .chain(save.held)
+        .chain([save.example_ball]);
+    if !sizes.all(valid_size) {
+        warn!("Ignoring the saved game, it has balls that are not in the catalog");
+        return;
+    }
+    let [x, y, z] = save.box_size;
+    *box_size = BoxSize { x, y, z };
+    // The box meshes are built at the starting size
+    for mut transform in box_query.iter_mut() {
+        transform.scale = Vec3::new(
+            x / config.box_size.x,
+            y / config.box_size.y,
+            z / config.box_size.z,
+        );
+    }
+    for (tag, mut collider) in walls.iter_mut() {
+        *collider = tag.collider(&box_size);
+    }
+    for saved in save.balls.iter() {
+        let mut ball = balls::Ball::new(saved.size, &ball_templates);
+        let scale = saved.scale.clamp(0.01, 1.0);
+        ball.spatial.transform = Transform::from_translation(Vec3::from_array(saved.translation))
+            .with_rotation(Quat::from_array(saved.rotation))
+            .with_scale(Vec3::splat(scale));
+        ball.collider.set_scale(Vec3::splat(scale), 0);
+        ball.vel = LinearVelocity(Vec3::from_array(saved.linear_velocity));
+        let mut entity = ball.spawn(&ball_templates, &mut commands);
+        entity.insert(AngularVelocity(Vec3::from_array(saved.angular_velocity)));
+        // Finish growing balls that were saved right after a merge
+        if scale < 1.0 {
+            let name = Name::new(format!("resumed{}", saved.size));
+            entity.insert((
+                name.clone(),
+                balls::growth_animation(&name, scale, &mut animations),
+            ));
+        }
+    }
+    balls::spawn_example_ball(
+        save.example_ball,
+        rng.as_mut(),
+        &ball_templates,
+        &mut commands,
+    )
+    .insert(
+        Transform::from_xyz(0.0, 4000.0, 0.0)
+            .with_rotation(Quat::from_array(save.example_rotation)),
+    );
+    queue.restore(save.upcoming, save.held, save.can_hold);
+    points.0 = save.points;
+    *stats = save.stats;
+    rng.resume(save.seed, save.rng_position);
+    next_state.set(GameState::Paused);
+    info!("Resumed the saved game");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save() -> SaveGame {
+        SaveGame {
+            version: SAVE_VERSION,
+            seed: 7,
+            rng_position: 12,
+            points: 120,
+            box_size: [4.0, 7.0, 6.0],
+            balls: vec![SavedBall {
+                size: 3,
+                translation: [1.0, -5.0, 0.5],
+                rotation: [0.0, 0.0, 0.0, 1.0],
+                scale: 0.8,
+                linear_velocity: [0.0, -1.0, 0.0],
+                angular_velocity: [0.2, 0.0, 0.0],
+            }],
+            example_ball: 2,
+            example_rotation: [0.0, 0.0, 0.0, 1.0],
+            upcoming: vec![1, 4, 2],
+            held: Some(3),
+            can_hold: false,
+            stats: RoundStats::default(),
+        }
+    }
+
+    #[test]
+    fn save_round_trips_through_ron() {
+        let text = ron::to_string(&save()).unwrap();
+        let parsed = SaveGame::parse(&text).unwrap();
+        assert_eq!(parsed.points, 120);
+        assert_eq!(parsed.balls[0].translation, [1.0, -5.0, 0.5]);
+        assert_eq!(parsed.held, Some(3));
+        assert_eq!(parsed.balls[0].scale, 0.8);
+        assert_eq!(parsed.rng_position, 12);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let text = ron::to_string(&SaveGame {
+            version: SAVE_VERSION + 1,
+            ..save()
+        })
+        .unwrap();
+        assert!(matches!(
+            SaveGame::parse(&text),
+            Err(SaveGameError::Version { found }) if found == SAVE_VERSION + 1
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::setup::{BoxScaleEvent, BoxSize, BoxTag};
+use crate::BallBlitzConfig;
 use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::Collider;
 
@@ -9,6 +10,7 @@ pub(crate) fn box_scale(
     mut query: Query<Entity, With<BoxTag>>,
     mut animations: ResMut<Assets<AnimationClip>>,
     mut q_walls: Query<(&crate::setup::WallTag, &mut Collider)>,
+    config: Res<BallBlitzConfig>,
 ) {
     if event.is_empty() {
         return;
@@ -16,28 +18,20 @@ pub(crate) fn box_scale(
     let new_size = event.read().next().unwrap();
     let ent = query.single_mut();
     // NOTE: doing this hack until the scaling issue is fixed in bevy_xpbd
+    let new_box_size = BoxSize {
+        x: new_size.x,
+        y: new_size.y,
+        z: new_size.z,
+    };
     for (tag, mut collider) in q_walls.iter_mut() {
-        *collider = Collider::cuboid(
-            2. * match tag.0 {
-                'x' => new_size.z,
-                'y' => new_size.x,
-                'z' => new_size.x,
-                _ => todo!(),
-            },
-            2. * match tag.0 {
-                'x' => new_size.y,
-                'y' => new_size.z,
-                'z' => new_size.y,
-                _ => todo!(),
-            },
-            0.01,
-        );
+        *collider = tag.collider(&new_box_size);
     }
     let name = Name::new("Box");
     commands.entity(ent).insert(name.clone()).insert({
         let mut player = AnimationPlayer::default();
         let mut animation = AnimationClip::default();
-        let default_box_size = BoxSize::default();
+        // The box meshes are built at the starting size
+        let default_box_size = config.box_size;
         let start = Vec3::new(
             box_size.x / default_box_size.x,
             box_size.y / default_box_size.y,
@@ -0,0 +1,565 @@
+use crate::actions::{self, Action, ActionInput, ActionMap};
+use crate::balls::BallTemplates;
+use crate::sound;
+use crate::state::{self, GameState};
+use crate::storage;
+use crate::widgets::{self, Slider};
+use crate::BallBlitzConfig;
+use bevy::pbr::DirectionalLightShadowMap;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Name of the stored settings file
+const SETTINGS_FILE: &str = "settings.ron";
+
+/// Preferences of the player, kept between launches
+/// Missing fields take their default so older settings files still load
+#[derive(Resource, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub graphics: GraphicsSettings,
+    pub camera: CameraSettings,
+    pub audio: AudioSettings,
+    pub accessibility: AccessibilitySettings,
+    pub controls: ActionMap,
+}
+
+/// Quality of shadows and ball meshes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// Size of the shadow maps in pixels, or 0 to turn shadows off
+    /// Unset until the player picks one, keeping the size of `BallBlitzConfig`
+    pub shadow_map_size: Option<usize>,
+    /// Multiplier of the number of faces of the ball spheres
+    pub sphere_detail: f32,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            shadow_map_size: None,
+            sphere_detail: 1.0,
+        }
+    }
+}
+
+/// Shadow map sizes the settings screen cycles through
+const SHADOW_MAP_SIZES: [(Option<usize>, &str); 5] = [
+    (None, "Default"),
+    (Some(0), "Off"),
+    (Some(1024), "Low"),
+    (Some(2048), "Medium"),
+    (Some(4096), "High"),
+];
+
+/// Sphere details the settings screen cycles through
+const SPHERE_DETAILS: [(f32, &str); 3] = [(0.5, "Low"), (1.0, "Medium"), (2.0, "High")];
+
+/// Largest orbit sensitivity of the slider
+const MAX_ORBIT_SENSITIVITY: f32 = 3.0;
+
+/// Largest orbit damping of the slider
+const MAX_ORBIT_DAMPING: f32 = 12.0;
+
+/// How dragging orbits the camera
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// Multiplier of the orbit speed
+    pub orbit_sensitivity: f32,
+    /// How quickly the camera stops orbiting after a flick, per second
+    pub orbit_damping: f32,
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            orbit_sensitivity: 1.0,
+            orbit_damping: 4.0,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+}
+
+/// Options making the game easier to read and watch
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilitySettings {
+    /// Scale all text and menus up
+    pub large_text: bool,
+    /// Keep the danger line steady instead of flashing
+    pub reduce_flashing: bool,
+}
+
+/// Volumes between 0 and 1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub effects: f32,
+    pub music: f32,
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 0.8,
+            effects: 1.0,
+            music: 0.4,
+            muted: false,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// Volume of sound effects, taking the master volume and mute into account
+    pub fn effects_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.master * self.effects
+        }
+    }
+    /// Volume of the music, taking the master volume and mute into account
+    pub fn music_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.master * self.music
+        }
+    }
+}
+
+impl Settings {
+    /// Read the stored settings, or use the defaults
+    pub fn load() -> Self {
+        let Some(text) = storage::read(SETTINGS_FILE) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable settings: {}", err);
+            Self::default()
+        })
+    }
+    /// Store the settings
+    pub fn save(&self) {
+        let result = ron::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|text| storage::write(SETTINGS_FILE, &text));
+        if let Err(err) = result {
+            warn!("Could not save settings: {}", err);
+        }
+    }
+}
+
+/// Loads the settings at launch, saves them whenever they change, and shows the settings screen
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load())
+            .insert_resource(SettingsReturn(GameState::Menu))
+            .init_resource::<actions::Rebinding>()
+            .add_systems(Last, save_settings.run_if(resource_changed::<Settings>()))
+            .add_systems(
+                Update,
+                (
+                    apply_graphics_settings.run_if(
+                        resource_changed::<Settings>()
+                            .or_else(resource_exists_and_changed::<BallTemplates>()),
+                    ),
+                    apply_accessibility_settings.run_if(resource_changed::<Settings>()),
+                ),
+            )
+            .add_systems(
+                OnEnter(GameState::Menu),
+                spawn_settings_button.after(state::spawn_menu_screen),
+            )
+            .add_systems(
+                OnEnter(GameState::Paused),
+                spawn_settings_button.after(state::spawn_pause_screen),
+            )
+            .add_systems(
+                Update,
+                open_settings_input
+                    .run_if(in_state(GameState::Menu).or_else(in_state(GameState::Paused))),
+            )
+            .add_systems(OnEnter(GameState::Settings), spawn_settings_screen)
+            .add_systems(OnExit(GameState::Settings), state::despawn_screen)
+            .add_systems(
+                Update,
+                (
+                    settings_input,
+                    close_settings_input.before(actions::rebind_input),
+                    actions::rebind_input,
+                    actions::update_control_labels.run_if(
+                        resource_changed::<Settings>()
+                            .or_else(resource_changed::<actions::Rebinding>()),
+                    ),
+                )
+                    .run_if(in_state(GameState::Settings)),
+            );
+    }
+}
+
+/// Store the settings when they changed, skipping the first run when they were just loaded
+fn save_settings(settings: Res<Settings>, mut saved: Local<Option<Settings>>) {
+    match saved.as_ref() {
+        None => *saved = Some(settings.clone()),
+        Some(saved_settings) if *saved_settings == *settings => {}
+        Some(_) => {
+            settings.save();
+            *saved = Some(settings.clone());
+        }
+    }
+}
+
+/// Apply the shadow quality and sphere detail
+fn apply_graphics_settings(
+    settings: Res<Settings>,
+    templates: Option<Res<BallTemplates>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+    mut lights: Query<&mut SpotLight>,
+    mut applied_detail: Local<Option<f32>>,
+    config: Res<BallBlitzConfig>,
+) {
+    let graphics = &settings.graphics;
+    let shadow_map_size = graphics.shadow_map_size.unwrap_or(config.shadow_map_size);
+    // Spot lights use the size of the directional light shadow maps
+    if shadow_map_size > 0 && shadow_map.size != shadow_map_size {
+        shadow_map.size = shadow_map_size;
+    }
+    for mut light in lights.iter_mut() {
+        light.shadows_enabled = shadow_map_size > 0;
+    }
+    // Rebuilding the spheres is slow, so only do it when the detail or the templates change
+    if let Some(templates) = templates {
+        if templates.is_changed() || *applied_detail != Some(graphics.sphere_detail) {
+            templates.set_sphere_detail(graphics.sphere_detail, &mut meshes);
+            *applied_detail = Some(graphics.sphere_detail);
+        }
+    }
+}
+
+/// Scale up the interface for large text
+fn apply_accessibility_settings(settings: Res<Settings>, mut ui_scale: ResMut<UiScale>) {
+    let scale = if settings.accessibility.large_text {
+        1.25
+    } else {
+        1.0
+    };
+    if ui_scale.0 != scale {
+        ui_scale.0 = scale;
+    }
+}
+
+/// State to go back to when the settings screen is closed
+#[derive(Resource)]
+pub struct SettingsReturn(GameState);
+
+/// Label struct for the button opening the settings screen
+#[derive(Component)]
+pub struct OpenSettingsButton;
+
+/// Label struct for the button closing the settings screen
+#[derive(Component)]
+pub struct CloseSettingsButton;
+
+/// Add a button opening the settings to the screen of the current state
+fn spawn_settings_button(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    screen: Query<Entity, With<state::StateScreen>>,
+) {
+    let Ok(screen) = screen.get_single() else {
+        return;
+    };
+    let style = widgets::menu_text_style(&asset_server);
+    commands.entity(screen).with_children(|parent| {
+        widgets::spawn_button(parent, &style, "Settings (O)", OpenSettingsButton);
+    });
+}
+
+/// Open the settings with the keys of the open settings action or the settings button
+fn open_settings_input(
+    actions: ActionInput,
+    button: Query<&Interaction, (With<OpenSettingsButton>, Changed<Interaction>)>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut settings_return: ResMut<SettingsReturn>,
+) {
+    let clicked = button
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    if actions.just_pressed(Action::OpenSettings) || clicked {
+        settings_return.0 = *state.get();
+        next_state.set(GameState::Settings);
+    }
+}
+
+/// Go back to the previous screen with Escape or the back button, unless an action is being
+/// rebound
+fn close_settings_input(
+    keys: Res<Input<KeyCode>>,
+    button: Query<&Interaction, (With<CloseSettingsButton>, Changed<Interaction>)>,
+    settings_return: Res<SettingsReturn>,
+    rebinding: Res<actions::Rebinding>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if rebinding.0.is_some() {
+        return;
+    }
+    let clicked = button
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    if keys.just_pressed(KeyCode::Escape) || clicked {
+        next_state.set(settings_return.0);
+    }
+}
+
+/// Option of the settings screen changed by clicking its button
+#[derive(Component, Clone, Copy)]
+pub enum SettingButton {
+    Shadows,
+    SphereDetail,
+    InvertX,
+    InvertY,
+    LargeText,
+    ReduceFlashing,
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "On"
+    } else {
+        "Off"
+    }
+}
+
+impl SettingButton {
+    /// Text of the button showing the current value
+    fn label(&self, settings: &Settings) -> String {
+        let graphics = &settings.graphics;
+        match self {
+            SettingButton::Shadows => {
+                let name = SHADOW_MAP_SIZES
+                    .iter()
+                    .find(|(size, _)| *size == graphics.shadow_map_size)
+                    .map_or("Custom", |(_, name)| name);
+                format!("Shadows: {}", name)
+            }
+            SettingButton::SphereDetail => {
+                let name = SPHERE_DETAILS
+                    .iter()
+                    .find(|(detail, _)| *detail == graphics.sphere_detail)
+                    .map_or("Custom", |(_, name)| name);
+                format!("Ball detail: {}", name)
+            }
+            SettingButton::InvertX => {
+                format!("Invert horizontal: {}", on_off(settings.camera.invert_x))
+            }
+            SettingButton::InvertY => {
+                format!("Invert vertical: {}", on_off(settings.camera.invert_y))
+            }
+            SettingButton::LargeText => {
+                format!("Large text: {}", on_off(settings.accessibility.large_text))
+            }
+            SettingButton::ReduceFlashing => format!(
+                "Reduce flashing: {}",
+                on_off(settings.accessibility.reduce_flashing)
+            ),
+        }
+    }
+    /// Switch to the next value
+    fn press(&self, settings: &mut Settings) {
+        let graphics = &mut settings.graphics;
+        match self {
+            SettingButton::Shadows => {
+                let index = SHADOW_MAP_SIZES
+                    .iter()
+                    .position(|(size, _)| *size == graphics.shadow_map_size)
+                    .map_or(0, |index| index + 1);
+                graphics.shadow_map_size = SHADOW_MAP_SIZES[index % SHADOW_MAP_SIZES.len()].0;
+            }
+            SettingButton::SphereDetail => {
+                let index = SPHERE_DETAILS
+                    .iter()
+                    .position(|(detail, _)| *detail == graphics.sphere_detail)
+                    .map_or(0, |index| index + 1);
+                graphics.sphere_detail = SPHERE_DETAILS[index % SPHERE_DETAILS.len()].0;
+            }
+            SettingButton::InvertX => settings.camera.invert_x = !settings.camera.invert_x,
+            SettingButton::InvertY => settings.camera.invert_y = !settings.camera.invert_y,
+            SettingButton::LargeText => {
+                settings.accessibility.large_text = !settings.accessibility.large_text
+            }
+            SettingButton::ReduceFlashing => {
+                settings.accessibility.reduce_flashing = !settings.accessibility.reduce_flashing
+            }
+        }
+    }
+}
+
+/// Label struct for the orbit sensitivity slider
+#[derive(Component)]
+pub struct OrbitSensitivitySlider;
+
+/// Label struct for the orbit damping slider
+#[derive(Component)]
+pub struct OrbitDampingSlider;
+
+/// Spawn a column of the settings screen with a heading
+fn spawn_section(
+    parent: &mut ChildBuilder,
+    style: &TextStyle,
+    heading: &str,
+    contents: impl FnOnce(&mut ChildBuilder),
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|section| {
+            section.spawn(TextBundle::from_section(
+                heading,
+                TextStyle {
+                    font_size: 28.0,
+                    ..style.clone()
+                },
+            ));
+            contents(section);
+        });
+}
+
+/// Spawn the settings screen, with graphics and camera options on the left, audio and
+/// accessibility options on the right, and the controls below
+fn spawn_settings_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    let style = widgets::menu_text_style(&asset_server);
+    let no_lines: [&str; 0] = [];
+    let screen = state::spawn_screen(&mut commands, &asset_server, "Settings", &no_lines);
+    let button = |parent: &mut ChildBuilder, setting: SettingButton| {
+        widgets::spawn_button(parent, &style, &setting.label(&settings), setting);
+    };
+    commands.entity(screen).with_children(|parent| {
+        parent
+            .spawn(NodeBundle {
+                style: Style {
+                    column_gap: Val::Px(60.0),
+                    align_items: AlignItems::FlexStart,
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|columns| {
+                columns
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            row_gap: Val::Px(25.0),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|column| {
+                        spawn_section(column, &style, "Graphics", |section| {
+                            button(section, SettingButton::Shadows);
+                            button(section, SettingButton::SphereDetail);
+                        });
+                        spawn_section(column, &style, "Camera", |section| {
+                            widgets::spawn_slider(
+                                section,
+                                &style,
+                                "Orbit speed",
+                                settings.camera.orbit_sensitivity / MAX_ORBIT_SENSITIVITY,
+                                OrbitSensitivitySlider,
+                            );
+                            widgets::spawn_slider(
+                                section,
+                                &style,
+                                "Orbit damping",
+                                settings.camera.orbit_damping / MAX_ORBIT_DAMPING,
+                                OrbitDampingSlider,
+                            );
+                            button(section, SettingButton::InvertX);
+                            button(section, SettingButton::InvertY);
+                        });
+                    });
+                columns
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            row_gap: Val::Px(25.0),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|column| {
+                        spawn_section(column, &style, "Audio", |section| {
+                            sound::spawn_audio_controls(section, &style, &settings.audio);
+                        });
+                        spawn_section(column, &style, "Accessibility", |section| {
+                            button(section, SettingButton::LargeText);
+                            button(section, SettingButton::ReduceFlashing);
+                        });
+                    });
+            });
+        spawn_section(parent, &style, "Controls", |section| {
+            actions::spawn_controls(section, &style, &settings.controls);
+        });
+        widgets::spawn_button(parent, &style, "Back (Escape)", CloseSettingsButton);
+    });
+}
+
+/// Apply the buttons and sliders of the settings screen, and keep the labels of the buttons up to
+/// date
+fn settings_input(
+    buttons: Query<(Ref<Interaction>, &SettingButton, &Children)>,
+    sensitivity: Query<&Slider, (With<OrbitSensitivitySlider>, Changed<Slider>)>,
+    damping: Query<&Slider, (With<OrbitDampingSlider>, Changed<Slider>)>,
+    mut texts: Query<&mut Text>,
+    mut settings: ResMut<Settings>,
+) {
+    for (interaction, setting, _) in buttons.iter() {
+        if interaction.is_changed() && *interaction == Interaction::Pressed {
+            setting.press(&mut settings);
+        }
+    }
+    for slider in sensitivity.iter() {
+        let value = (slider.value * MAX_ORBIT_SENSITIVITY).max(0.1);
+        if settings.camera.orbit_sensitivity != value {
+            settings.camera.orbit_sensitivity = value;
+        }
+    }
+    for slider in damping.iter() {
+        // Without damping the camera would never stop
+        let value = (slider.value * MAX_ORBIT_DAMPING).max(0.5);
+        if settings.camera.orbit_damping != value {
+            settings.camera.orbit_damping = value;
+        }
+    }
+    if settings.is_changed() {
+        for (_, setting, children) in buttons.iter() {
+            for child in children.iter() {
+                if let Ok(mut text) = texts.get_mut(*child) {
+                    text.sections[0].value = setting.label(&settings);
+                }
+            }
+        }
+    }
+}
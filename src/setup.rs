@@ -1,8 +1,25 @@
-use crate::{balls, camera};
+use crate::rng::GameRng;
+use crate::state::GameState;
+use crate::{balls, points, scene_scale, BallBlitzConfig};
 use bevy::{prelude::*, render::render_resource::PrimitiveTopology};
 use bevy_xpbd_3d::{math::PI, prelude::*};
 
-#[derive(Resource)]
+/// Box holding the balls, with its walls and lights
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BoxScaleEvent>()
+            .add_systems(PostStartup, setup)
+            .add_systems(
+                Update,
+                scene_scale::box_scale.run_if(on_event::<BoxScaleEvent>()),
+            );
+    }
+}
+
+/// Half size of the box along each axis
+#[derive(Resource, Clone, Copy, Debug)]
 pub struct BoxSize {
     pub x: f32,
     pub y: f32,
@@ -29,7 +46,28 @@ pub struct BoxScaleEvent {
 pub struct BoxTag(pub ());
 
 #[derive(Component)]
-pub struct WallTag(pub char, pub f32);
+pub struct WallTag(pub char);
+
+impl WallTag {
+    /// Return the width and height of the wall for a specific box size
+    fn dimensions(&self, box_size: &BoxSize) -> (f32, f32) {
+        match self.0 {
+            'x' => (2. * box_size.z, 2. * box_size.y),
+            'y' => (2. * box_size.x, 2. * box_size.z),
+            'z' => (2. * box_size.x, 2. * box_size.y),
+            ch => panic!("Unexpected direction {}", ch),
+        }
+    }
+    /// Return the collider of the wall for a specific box size
+    pub fn collider(&self, box_size: &BoxSize) -> Collider {
+        let (width, height) = self.dimensions(box_size);
+        Collider::cuboid(width, height, 0.01)
+    }
+}
+
+/// Sent to tear down the current round and start a new one
+#[derive(Event)]
+pub struct NewGameEvent;
 
 #[derive(Bundle)]
 struct WallBundle {
@@ -54,29 +92,17 @@ impl WallBundle {
             'z' => Vec3::new(0., 0., side * box_size.z),
             ch => panic!("Unexpected direction {}", ch),
         };
-        let width = 2.
-            * match direction {
-                'x' => box_size.z,
-                'y' => box_size.x,
-                'z' => box_size.x,
-                ch => panic!("Unexpected direction {}", ch),
-            };
-        let height = 2.
-            * match direction {
-                'x' => box_size.y,
-                'y' => box_size.z,
-                'z' => box_size.y,
-                ch => panic!("Unexpected direction {}", ch),
-            };
+        let tag = WallTag(direction);
+        let (width, height) = tag.dimensions(box_size);
         let rotation = if direction == 'y' {
             Quat::from_rotation_x(-side * PI / 2.)
         } else {
             Quat::from_rotation_y(if direction == 'x' { side } else { side - 1. } * PI / 2.)
         };
-        return Self {
+        Self {
             shadows: bevy::pbr::NotShadowCaster {},
             rigid_body: RigidBody::Static,
-            collider: Collider::cuboid(width, height, 0.01),
+            collider: tag.collider(box_size),
             pbr_bundle: PbrBundle {
                 mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::new(width, height)))),
                 transform: Transform {
@@ -87,8 +113,8 @@ impl WallBundle {
                 material,
                 ..default()
             },
-            tag: WallTag(direction, side),
-        };
+            tag,
+        }
     }
 }
 
@@ -96,30 +122,58 @@ pub(crate) fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    ball_templates: Res<balls::BallTemplates>,
+    config: Res<BallBlitzConfig>,
 ) {
-    spawn_box(&mut commands, &mut materials, &mut meshes);
+    spawn_box(&mut commands, &mut materials, &mut meshes, config.box_size);
     spawn_lights(&mut commands);
-    commands.spawn(camera::new_camera());
-    let mut example_ball = balls::Ball::new(1);
-    example_ball.spatial.transform.translation = Vec3::new(0.0, 400000.0, 0.0);
-    example_ball
-        .spawn(&ball_templates, &mut commands)
-        .insert(balls::ExampleBall(()))
-        .insert(GravityScale(0.0))
-        .insert(RigidBody::Kinematic);
+}
+
+/// Tear down the previous round and start a new one
+/// Despawns every ball, resets the box to its starting size and the points to zero, then restarts
+/// the random generator, refills the ball queue and spawns a new example ball
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn new_game(
+    mut commands: Commands,
+    balls: Query<Entity, With<balls::BallSize>>,
+    mut box_query: Query<(Entity, &mut Transform), With<BoxTag>>,
+    mut walls: Query<(&WallTag, &mut Collider)>,
+    mut box_size: ResMut<BoxSize>,
+    mut points: ResMut<points::GamePoints>,
+    mut next_state: ResMut<NextState<GameState>>,
+    ball_templates: Res<balls::BallTemplates>,
+    mut queue: ResMut<balls::BallQueue>,
+    mut rng: ResMut<GameRng>,
+    config: Res<BallBlitzConfig>,
+) {
+    for ent in balls.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    // Stop any box growing animation and shrink the box back
+    *box_size = config.box_size;
+    for (ent, mut transform) in box_query.iter_mut() {
+        commands.entity(ent).remove::<AnimationPlayer>();
+        transform.scale = Vec3::ONE;
+    }
+    for (tag, mut collider) in walls.iter_mut() {
+        *collider = tag.collider(&box_size);
+    }
+    points.0 = 0;
+    next_state.set(GameState::Playing);
+    rng.restart();
+    queue.reset(rng.as_mut());
+    let size = queue.pop_next(rng.as_mut());
+    balls::spawn_example_ball(size, rng.as_mut(), &ball_templates, &mut commands);
 }
 
 /// Creates a ball-holding box with walls and a base
-/// The inner width of the box is 8 units, each wall is 0.5 units thick
-/// The height of the box is 12 units
-fn spawn_box(
+/// By default the inner width of the box is 8 units and its height is 12 units
+pub(crate) fn spawn_box(
     commands: &mut Commands,
     materials: &mut ResMut<Assets<StandardMaterial>>,
     meshes: &mut ResMut<Assets<Mesh>>,
+    box_size: BoxSize,
 ) {
     let mut bundles: Vec<Entity> = vec![];
-    let box_size = BoxSize::default();
     let wall_mat = materials.add(StandardMaterial {
         alpha_mode: AlphaMode::Blend,
         base_color: Color::rgba(0.3, 0.3, 0.3, 0.12),
@@ -0,0 +1,515 @@
+use crate::actions::{self, Action, ActionInput};
+use crate::balls::{BallDropped, BallMerged, BallSize, FinalBallsVanished};
+use crate::points::BallLost;
+use crate::settings::{AudioSettings, Settings};
+use crate::state::GameState;
+use crate::widgets::{self, Slider};
+use bevy::audio::{AddAudioSource, AudioSink, Decodable, Source, Volume};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_xpbd_3d::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Samples per second of the generated sounds
+const SAMPLE_RATE: u32 = 44100;
+
+/// Impulse of a collision that plays at full volume
+const FULL_VOLUME_IMPULSE: f32 = 40.0;
+
+/// Collisions with a smaller impulse are silent, so resting balls don't rattle
+const MIN_IMPULSE: f32 = 0.5;
+
+/// Most impact sounds started in one frame, the loudest ones are kept
+const MAX_IMPACTS_PER_FRAME: usize = 4;
+
+/// Sound effects of collisions, merges, drops and lost balls, and background music
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_audio_source::<Synth>()
+            .init_resource::<SoundLibrary>()
+            .add_systems(Startup, start_music)
+            .add_systems(
+                Update,
+                (
+                    play_impacts,
+                    play_event_sounds,
+                    track_velocities.after(play_impacts),
+                )
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    // Before the rebinding takes the key, so binding it to mute doesn't mute
+                    mute_input.before(actions::rebind_input),
+                    audio_settings_input,
+                    update_music_volume.run_if(resource_changed::<Settings>()),
+                ),
+            );
+    }
+}
+
+/// Shape of the wave of a note
+#[derive(Clone, Copy, Debug)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Square,
+}
+
+/// A note of a generated sound, gliding from one frequency to another as it decays
+#[derive(Clone, Copy, Debug)]
+pub struct Note {
+    /// Seconds from the start of the sound
+    pub start: f32,
+    pub duration: f32,
+    pub frequency: f32,
+    pub end_frequency: f32,
+    pub waveform: Waveform,
+    pub gain: f32,
+}
+
+impl Note {
+    fn new(start: f32, duration: f32, frequency: f32, waveform: Waveform, gain: f32) -> Self {
+        Self {
+            start,
+            duration,
+            frequency,
+            end_frequency: frequency,
+            waveform,
+            gain,
+        }
+    }
+    fn glide_to(self, end_frequency: f32) -> Self {
+        Self {
+            end_frequency,
+            ..self
+        }
+    }
+    /// Value of the note at a time from the start of the sound
+    fn sample(&self, time: f32) -> f32 {
+        let t = time - self.start;
+        if t < 0.0 || t >= self.duration {
+            return 0.0;
+        }
+        let progress = t / self.duration;
+        // Phase of a linear glide, the integral of the frequency over time
+        let phase = t * (self.frequency + (self.end_frequency - self.frequency) * progress / 2.0);
+        let wave = match self.waveform {
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 4.0 * (phase.fract() - 0.5).abs() - 1.0,
+            Waveform::Square => (phase.fract() - 0.5).signum() * 0.5,
+        };
+        // Short attack to avoid clicks, then an exponential decay
+        let attack = (t / 0.005).min(1.0);
+        let decay = (-4.0 * progress).exp() * (1.0 - progress);
+        wave * attack * decay * self.gain
+    }
+}
+
+/// Sound generated from a list of notes
+#[derive(Asset, TypePath, Clone)]
+pub struct Synth {
+    notes: Arc<[Note]>,
+    duration: f32,
+}
+
+impl Synth {
+    pub fn new(notes: Vec<Note>) -> Self {
+        let duration = notes
+            .iter()
+            .map(|note| note.start + note.duration)
+            .fold(0.0, f32::max);
+        Self {
+            notes: notes.into(),
+            duration,
+        }
+    }
+}
+
+/// Iterator over the samples of a [`Synth`]
+pub struct SynthDecoder {
+    notes: Arc<[Note]>,
+    sample: u32,
+    samples: u32,
+}
+
+impl Iterator for SynthDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.sample >= self.samples {
+            return None;
+        }
+        let time = self.sample as f32 / SAMPLE_RATE as f32;
+        self.sample += 1;
+        Some(self.notes.iter().map(|note| note.sample(time)).sum())
+    }
+}
+
+impl Source for SynthDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(
+            self.samples as f32 / SAMPLE_RATE as f32,
+        ))
+    }
+}
+
+impl Decodable for Synth {
+    type DecoderItem = f32;
+    type Decoder = SynthDecoder;
+
+    fn decoder(&self) -> SynthDecoder {
+        SynthDecoder {
+            notes: self.notes.clone(),
+            sample: 0,
+            samples: (self.duration * SAMPLE_RATE as f32) as u32,
+        }
+    }
+}
+
+/// Frequency of a MIDI note number
+fn midi(note: i32) -> f32 {
+    440.0 * 2f32.powf((note - 69) as f32 / 12.0)
+}
+
+/// Notes of the major pentatonic scale from middle C, one per ball size
+fn size_note(size: u8) -> i32 {
+    const SCALE: [i32; 5] = [0, 2, 4, 7, 9];
+    let step = size as usize - 1;
+    60 + 12 * (step / SCALE.len()) as i32 + SCALE[step % SCALE.len()]
+}
+
+/// Short knock, lower for larger balls
+fn impact_sound(size: u8) -> Synth {
+    let frequency = 900.0 / (1.0 + 0.3 * (size as f32 - 1.0));
+    Synth::new(vec![
+        Note::new(0.0, 0.12, frequency, Waveform::Sine, 0.6).glide_to(frequency * 0.7),
+        Note::new(0.0, 0.05, frequency * 2.7, Waveform::Triangle, 0.15),
+    ])
+}
+
+/// Rising two note chime, higher for larger balls
+fn merge_sound(size: u8) -> Synth {
+    let note = size_note(size);
+    Synth::new(vec![
+        Note::new(0.0, 0.25, midi(note), Waveform::Triangle, 0.4),
+        Note::new(0.08, 0.4, midi(note + 7), Waveform::Triangle, 0.4),
+        Note::new(0.08, 0.4, midi(note + 12), Waveform::Sine, 0.2),
+    ])
+}
+
+/// Chord played when two balls of the final tier vanish
+fn final_sound() -> Synth {
+    Synth::new(
+        [60, 64, 67, 72, 76]
+            .iter()
+            .enumerate()
+            .map(|(i, note)| Note::new(i as f32 * 0.07, 1.0, midi(*note), Waveform::Triangle, 0.25))
+            .collect(),
+    )
+}
+
+/// Soft downward swoosh
+fn drop_sound() -> Synth {
+    Synth::new(vec![
+        Note::new(0.0, 0.15, 500.0, Waveform::Sine, 0.4).glide_to(200.0)
+    ])
+}
+
+/// Falling buzz
+fn penalty_sound() -> Synth {
+    Synth::new(vec![
+        Note::new(0.0, 0.5, 300.0, Waveform::Square, 0.3).glide_to(100.0),
+        Note::new(0.0, 0.5, 303.0, Waveform::Square, 0.2).glide_to(98.0),
+    ])
+}
+
+/// Calm arpeggios over C, A minor, F and G, looped
+fn music() -> Synth {
+    const BEAT: f32 = 0.3;
+    let chords = [
+        [48, 55, 60, 64],
+        [45, 52, 57, 60],
+        [41, 48, 53, 57],
+        [43, 50, 55, 59],
+    ];
+    let mut notes = vec![];
+    for (bar, chord) in chords.iter().enumerate() {
+        let bar_start = bar as f32 * 8.0 * BEAT;
+        notes.push(Note::new(
+            bar_start,
+            8.0 * BEAT,
+            midi(chord[0] - 12),
+            Waveform::Sine,
+            0.3,
+        ));
+        for beat in 0..8 {
+            let note = chord[[1, 2, 3, 2, 1, 2, 3, 2][beat]];
+            let start = bar_start + beat as f32 * BEAT;
+            notes.push(Note::new(
+                start,
+                BEAT * 2.0,
+                midi(note),
+                Waveform::Triangle,
+                0.12,
+            ));
+        }
+    }
+    let mut synth = Synth::new(notes);
+    // Loop on the bar instead of the tail of the last notes
+    synth.duration = chords.len() as f32 * 8.0 * BEAT;
+    synth
+}
+
+/// Handles of the generated sounds, built the first time they are played
+#[derive(Resource, Default)]
+pub struct SoundLibrary {
+    impacts: HashMap<u8, Handle<Synth>>,
+    merges: HashMap<u8, Handle<Synth>>,
+    others: HashMap<&'static str, Handle<Synth>>,
+}
+
+impl SoundLibrary {
+    fn impact(&mut self, size: u8, assets: &mut Assets<Synth>) -> Handle<Synth> {
+        self.impacts
+            .entry(size)
+            .or_insert_with(|| assets.add(impact_sound(size)))
+            .clone()
+    }
+    fn merge(&mut self, size: u8, assets: &mut Assets<Synth>) -> Handle<Synth> {
+        self.merges
+            .entry(size)
+            .or_insert_with(|| assets.add(merge_sound(size)))
+            .clone()
+    }
+    fn other(
+        &mut self,
+        name: &'static str,
+        sound: fn() -> Synth,
+        assets: &mut Assets<Synth>,
+    ) -> Handle<Synth> {
+        self.others
+            .entry(name)
+            .or_insert_with(|| assets.add(sound()))
+            .clone()
+    }
+}
+
+/// Spawn a sound that despawns once played
+fn play(commands: &mut Commands, source: Handle<Synth>, volume: f32) {
+    if volume <= 0.0 {
+        return;
+    }
+    commands.spawn(AudioSourceBundle {
+        source,
+        settings: PlaybackSettings::DESPAWN.with_volume(Volume::new_absolute(volume)),
+    });
+}
+
+/// Velocity of a ball at the end of the previous frame, to measure the impulse of collisions
+#[derive(Component)]
+pub struct LastVelocity(Vec3);
+
+/// Balls whose velocity isn't tracked yet
+type Untracked = (With<BallSize>, Without<LastVelocity>);
+
+/// Remember the velocity of every ball for the next frame
+fn track_velocities(
+    mut commands: Commands,
+    mut tracked: Query<(&LinearVelocity, &mut LastVelocity)>,
+    untracked: Query<(Entity, &LinearVelocity), Untracked>,
+) {
+    for (velocity, mut last) in tracked.iter_mut() {
+        last.0 = velocity.0;
+    }
+    for (ent, velocity) in untracked.iter() {
+        commands.entity(ent).insert(LastVelocity(velocity.0));
+    }
+}
+
+/// Play a knock for balls that started touching, as loud as the impulse of the collision
+/// The impulse is the change of momentum of the ball since the previous frame
+fn play_impacts(
+    mut commands: Commands,
+    mut started: EventReader<CollisionStarted>,
+    balls: Query<(&BallSize, &Mass, &LinearVelocity, &LastVelocity)>,
+    settings: Res<Settings>,
+    mut library: ResMut<SoundLibrary>,
+    mut assets: ResMut<Assets<Synth>>,
+) {
+    let mut impacts: Vec<(u8, f32)> = started
+        .read()
+        .flat_map(|CollisionStarted(a, b)| [*a, *b])
+        .filter_map(|ent| balls.get(ent).ok())
+        .map(|(size, mass, velocity, last)| (size.0, mass.0 * (velocity.0 - last.0).length()))
+        .filter(|(_, impulse)| *impulse > MIN_IMPULSE)
+        .collect();
+    impacts.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (size, impulse) in impacts.into_iter().take(MAX_IMPACTS_PER_FRAME) {
+        let loudness = (impulse / FULL_VOLUME_IMPULSE).sqrt().min(1.0);
+        let source = library.impact(size, &mut assets);
+        play(
+            &mut commands,
+            source,
+            loudness * settings.audio.effects_volume(),
+        );
+    }
+}
+
+/// Play the sounds of merges, drops and lost balls
+#[allow(clippy::too_many_arguments)]
+fn play_event_sounds(
+    mut commands: Commands,
+    mut merged: EventReader<BallMerged>,
+    mut vanished: EventReader<FinalBallsVanished>,
+    mut dropped: EventReader<BallDropped>,
+    mut lost: EventReader<BallLost>,
+    settings: Res<Settings>,
+    mut library: ResMut<SoundLibrary>,
+    mut assets: ResMut<Assets<Synth>>,
+) {
+    let volume = settings.audio.effects_volume();
+    for merge in merged.read() {
+        let source = library.merge(merge.size, &mut assets);
+        play(&mut commands, source, volume);
+    }
+    for _ in vanished.read() {
+        let source = library.other("final", final_sound, &mut assets);
+        play(&mut commands, source, volume);
+    }
+    for _ in dropped.read() {
+        let source = library.other("drop", drop_sound, &mut assets);
+        play(&mut commands, source, volume);
+    }
+    for _ in lost.read() {
+        let source = library.other("penalty", penalty_sound, &mut assets);
+        play(&mut commands, source, volume);
+    }
+}
+
+/// Label struct for the background music
+#[derive(Component)]
+pub struct Music;
+
+fn start_music(mut commands: Commands, mut assets: ResMut<Assets<Synth>>, settings: Res<Settings>) {
+    commands.spawn((
+        AudioSourceBundle {
+            source: assets.add(music()),
+            settings: PlaybackSettings::LOOP
+                .with_volume(Volume::new_absolute(settings.audio.music_volume())),
+        },
+        Music,
+    ));
+}
+
+fn update_music_volume(music: Query<&AudioSink, With<Music>>, settings: Res<Settings>) {
+    for sink in music.iter() {
+        sink.set_volume(settings.audio.music_volume());
+    }
+}
+
+/// Toggle mute with the keys of the mute action
+fn mute_input(actions: ActionInput, mut settings: ResMut<Settings>) {
+    if actions.just_pressed(Action::Mute) {
+        settings.audio.muted = !settings.audio.muted;
+    }
+}
+
+/// Volume a slider of the audio panel controls
+#[derive(Component, Clone, Copy)]
+pub enum VolumeSlider {
+    Master,
+    Effects,
+    Music,
+}
+
+/// Label struct for the mute button of the audio panel
+#[derive(Component)]
+pub struct MuteButton;
+
+fn mute_label(muted: bool) -> &'static str {
+    if muted {
+        "Unmute (M)"
+    } else {
+        "Mute (M)"
+    }
+}
+
+/// Spawn the volume sliders and the mute button
+pub fn spawn_audio_controls(parent: &mut ChildBuilder, style: &TextStyle, audio: &AudioSettings) {
+    widgets::spawn_slider(parent, style, "Volume", audio.master, VolumeSlider::Master);
+    widgets::spawn_slider(
+        parent,
+        style,
+        "Effects",
+        audio.effects,
+        VolumeSlider::Effects,
+    );
+    widgets::spawn_slider(parent, style, "Music", audio.music, VolumeSlider::Music);
+    widgets::spawn_button(parent, style, mute_label(audio.muted), MuteButton);
+}
+
+/// Apply the volume sliders and mute button to the settings, and keep the label of
+/// the mute button up to date
+fn audio_settings_input(
+    sliders: Query<(&Slider, &VolumeSlider), Changed<Slider>>,
+    mute_button: Query<(Ref<Interaction>, &Children), With<MuteButton>>,
+    mut texts: Query<&mut Text>,
+    mut settings: ResMut<Settings>,
+) {
+    for (slider, volume) in sliders.iter() {
+        let audio = &mut settings.audio;
+        let target = match volume {
+            VolumeSlider::Master => &mut audio.master,
+            VolumeSlider::Effects => &mut audio.effects,
+            VolumeSlider::Music => &mut audio.music,
+        };
+        if *target != slider.value {
+            *target = slider.value;
+        }
+    }
+    for (interaction, _) in mute_button.iter() {
+        if interaction.is_changed() && *interaction == Interaction::Pressed {
+            settings.audio.muted = !settings.audio.muted;
+        }
+    }
+    if settings.is_changed() {
+        let label = mute_label(settings.audio.muted);
+        for (_, children) in mute_button.iter() {
+            for child in children.iter() {
+                if let Ok(mut text) = texts.get_mut(*child) {
+                    text.sections[0].value = label.to_owned();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synth_plays_until_its_last_note_ends() {
+        let synth = Synth::new(vec![
+            Note::new(0.0, 0.1, 440.0, Waveform::Sine, 0.5),
+            Note::new(0.2, 0.3, 220.0, Waveform::Square, 0.5),
+        ]);
+        let samples: Vec<f32> = synth.decoder().collect();
+        assert_eq!(samples.len(), (0.5 * SAMPLE_RATE as f32) as usize);
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+    }
+}
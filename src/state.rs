@@ -0,0 +1,283 @@
+use crate::actions::{Action, ActionInput};
+use crate::balls::{self, BallCatalogHandle, BallTemplates};
+use crate::points::GamePoints;
+use crate::rng::GameRng;
+use crate::settings::Settings;
+use crate::setup::{self, NewGameEvent};
+use crate::stats::{self, RoundOutcome, RoundStats};
+use crate::widgets::MenuButton;
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+/// Lifecycle of the game, from the title screen to the end of a round
+#[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GameState {
+    /// Waiting for the ball catalog to load
+    #[default]
+    Loading,
+    /// Title screen shown once loaded
+    Menu,
+    /// A round is in progress
+    Playing,
+    /// The round is frozen, including physics time and animations
+    Paused,
+    /// The round has ended and is waiting for a restart
+    GameOver,
+    /// Settings screen, opened from the title or pause screen
+    Settings,
+}
+
+/// Game states, starting and ending rounds, pausing, and the statistics of each round
+pub struct StatePlugin;
+
+impl Plugin for StatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<GameState>()
+            .add_systems(
+                Update,
+                finish_loading
+                    .after(balls::build_ball_templates)
+                    .run_if(in_state(GameState::Loading)),
+            )
+            .add_systems(OnEnter(GameState::Paused), pause_world)
+            // Resumed when playing again rather than when leaving the pause screen, so the world
+            // stays frozen while the settings are open
+            .add_systems(OnEnter(GameState::Playing), resume_world)
+            .add_event::<NewGameEvent>()
+            .add_systems(
+                Update,
+                (setup::new_game, stats::reset_stats).run_if(on_event::<NewGameEvent>()),
+            )
+            .init_resource::<RoundStats>()
+            .add_systems(
+                Update,
+                (stats::track_stats, stats::check_round_end)
+                    .after(crate::points::apply_points)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Label struct for the overlay shown in a non-playing state
+#[derive(Component)]
+pub struct StateScreen;
+
+/// Spawn a full screen overlay with a title and a list of lines below it, and return the
+/// overlay
+pub(crate) fn spawn_screen(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    title: &str,
+    lines: &[impl AsRef<str>],
+) -> Entity {
+    let font = asset_server.load("fonts/mononoki-Regular.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            StateScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                title,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            ));
+            for line in lines {
+                parent.spawn(TextBundle::from_section(
+                    line.as_ref(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 25.0,
+                        color: Color::WHITE,
+                    },
+                ));
+            }
+        })
+        .id()
+}
+
+/// Spawn the loading screen
+pub fn spawn_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_screen(&mut commands, &asset_server, "Ball Blitz", &["Loading..."]);
+}
+
+/// Go to the title screen once the ball templates are built from the catalog
+pub fn finish_loading(
+    templates: Option<Res<BallTemplates>>,
+    catalog: Option<Res<BallCatalogHandle>>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut reported: Local<bool>,
+) {
+    if templates.is_some() {
+        next_state.set(GameState::Menu);
+    } else if catalog
+        .is_some_and(|catalog| asset_server.load_state(&catalog.0) == LoadState::Failed)
+        && !*reported
+    {
+        error!(
+            "Failed to load the ball catalog from {}",
+            balls::CATALOG_PATH
+        );
+        *reported = true;
+    }
+}
+
+/// Spawn the title screen
+pub fn spawn_menu_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_screen(
+        &mut commands,
+        &asset_server,
+        "Ball Blitz",
+        &["Click or press Space to start"],
+    );
+}
+
+/// Spawn the pause screen, naming the current bindings of its actions
+pub fn spawn_pause_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    let lines: Vec<String> = [
+        (Action::Pause, "resume"),
+        (Action::Restart, "start a new game"),
+        (Action::EndRound, "end the round"),
+    ]
+    .into_iter()
+    .filter_map(|(action, what)| {
+        let keys = settings.controls.prompt(action)?;
+        Some(format!("Press {} to {}", keys, what))
+    })
+    .collect();
+    spawn_screen(&mut commands, &asset_server, "Paused", &lines);
+}
+
+/// Spawn the end of round screen with a summary of the round
+pub fn spawn_game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    stats: Res<RoundStats>,
+    points: Res<GamePoints>,
+    templates: Res<BallTemplates>,
+    rng: Res<GameRng>,
+) {
+    let title = match stats.outcome {
+        Some(RoundOutcome::Won) => "You win!",
+        Some(RoundOutcome::Lost) => "Game over",
+        Some(RoundOutcome::Overflowed) => "The box overflowed",
+        None => "Round over",
+    };
+    let mut lines = vec![
+        format!("Final score: {}", points.0),
+        format!("Time played: {}", stats.time_played_text()),
+        format!("Balls dropped: {}", stats.balls_dropped),
+        format!("Balls lost: {}", stats.balls_lost),
+        format!("Seed: {}", rng.seed()),
+    ];
+    if stats.merges.is_empty() {
+        lines.push("Merges: none".to_owned());
+    } else {
+        lines.push("Merges:".to_owned());
+        for (size, count) in stats.merges.iter() {
+            lines.push(format!("{}: {}", templates.tier(*size).name, count));
+        }
+    }
+    lines.push(String::new());
+    lines.push("Click or press Space to play again".to_owned());
+    spawn_screen(&mut commands, &asset_server, title, &lines);
+}
+
+/// Despawn the overlay of the state that is being exited
+pub fn despawn_screen(mut commands: Commands, query: Query<Entity, With<StateScreen>>) {
+    for ent in query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+/// Returns true if the player confirmed a screen with a key, click, or tap
+fn confirm_pressed(keys: &Input<KeyCode>, buttons: &Input<MouseButton>, touches: &Touches) -> bool {
+    // Released instead of pressed so the click doesn't also drop a ball once the round starts
+    keys.any_just_pressed([KeyCode::Space, KeyCode::Return])
+        || buttons.just_released(MouseButton::Left)
+        || touches.any_just_released()
+}
+
+/// Start a round from the title screen or restart it from the end screen
+/// Clicks on the buttons of the screen are left to the buttons
+pub fn start_round_input(
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    touches: Res<Touches>,
+    menu_buttons: Query<&Interaction, With<MenuButton>>,
+    mut new_game: EventWriter<NewGameEvent>,
+) {
+    if menu_buttons
+        .iter()
+        .any(|interaction| *interaction != Interaction::None)
+    {
+        return;
+    }
+    if confirm_pressed(&keys, &buttons, &touches) {
+        new_game.send(NewGameEvent);
+    }
+}
+
+/// Toggle between playing and paused, end the round from the pause screen, or start a new game
+pub fn pause_input(
+    actions: ActionInput,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut new_game: EventWriter<NewGameEvent>,
+) {
+    if actions.just_pressed(Action::Restart) {
+        new_game.send(NewGameEvent);
+        return;
+    }
+    match state.get() {
+        GameState::Playing if actions.just_pressed(Action::Pause) => {
+            next_state.set(GameState::Paused)
+        }
+        GameState::Paused if actions.just_pressed(Action::Pause) => {
+            next_state.set(GameState::Playing)
+        }
+        GameState::Paused if actions.just_pressed(Action::EndRound) => {
+            next_state.set(GameState::GameOver)
+        }
+        _ => {}
+    }
+}
+
+/// Freeze physics time and animations while paused
+pub fn pause_world(mut time: ResMut<Time<Physics>>, mut players: Query<&mut AnimationPlayer>) {
+    time.pause();
+    for mut player in players.iter_mut() {
+        player.pause();
+    }
+}
+
+/// Resume physics time and animations after a pause
+pub fn resume_world(mut time: ResMut<Time<Physics>>, mut players: Query<&mut AnimationPlayer>) {
+    time.unpause();
+    for mut player in players.iter_mut() {
+        player.resume();
+    }
+}
@@ -0,0 +1,93 @@
+use crate::balls::{BallDropped, BallMerged, BallTemplates};
+use crate::points::{BallLost, GamePoints};
+use crate::state::GameState;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How a round ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoundOutcome {
+    /// A ball of the final tier was created
+    Won,
+    /// The points went below zero
+    Lost,
+    /// Balls stayed above the danger line until the countdown ran out
+    Overflowed,
+}
+
+/// Statistics of the current round, shown on the end screen
+#[derive(Resource, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct RoundStats {
+    /// Seconds spent playing, not counting pauses
+    pub time_played: f32,
+    pub balls_dropped: u32,
+    /// Number of merges keyed by the size of the ball they created
+    pub merges: BTreeMap<u8, u32>,
+    pub balls_lost: u32,
+    /// Largest ball dropped or created by a merge
+    pub largest_ball: u8,
+    /// Number of balls dropped when the first ball fell out of the box
+    pub drops_until_overflow: Option<u32>,
+    /// None if the round was ended from the pause screen
+    pub outcome: Option<RoundOutcome>,
+}
+
+impl RoundStats {
+    /// Time played formatted as minutes and seconds
+    pub fn time_played_text(&self) -> String {
+        let seconds = self.time_played as u32;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Reset the statistics at the start of a round
+pub fn reset_stats(mut stats: ResMut<RoundStats>) {
+    *stats = RoundStats::default();
+}
+
+/// Count time played, drops, merges, lost balls and the largest ball
+pub fn track_stats(
+    time: Res<Time>,
+    mut stats: ResMut<RoundStats>,
+    mut dropped: EventReader<BallDropped>,
+    mut merged: EventReader<BallMerged>,
+    mut lost: EventReader<BallLost>,
+) {
+    stats.time_played += time.delta_seconds();
+    for drop in dropped.read() {
+        stats.balls_dropped += 1;
+        stats.largest_ball = stats.largest_ball.max(drop.size);
+    }
+    for merge in merged.read() {
+        *stats.merges.entry(merge.size).or_insert(0) += 1;
+        stats.largest_ball = stats.largest_ball.max(merge.size);
+    }
+    let lost = lost.read().count() as u32;
+    if lost > 0 && stats.drops_until_overflow.is_none() {
+        stats.drops_until_overflow = Some(stats.balls_dropped);
+    }
+    stats.balls_lost += lost;
+}
+
+/// End the round when a ball of the final tier is created or the points go negative
+pub fn check_round_end(
+    mut merged: EventReader<BallMerged>,
+    points: Res<GamePoints>,
+    templates: Res<BallTemplates>,
+    mut stats: ResMut<RoundStats>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let outcome = if merged
+        .read()
+        .any(|merge| merge.size == templates.final_tier())
+    {
+        RoundOutcome::Won
+    } else if points.0 < 0 {
+        RoundOutcome::Lost
+    } else {
+        return;
+    };
+    stats.outcome = Some(outcome);
+    next_state.set(GameState::GameOver);
+}
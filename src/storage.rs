@@ -0,0 +1,90 @@
+//! Small text files kept between launches, in the platform data directory on native and in
+//! `localStorage` on the web
+
+/// Read a stored file, or None if it was never written or can't be read
+pub fn read(name: &str) -> Option<String> {
+    platform::read(name)
+}
+
+/// Write a stored file, replacing its contents
+pub fn write(name: &str, contents: &str) -> Result<(), String> {
+    platform::write(name, contents)
+}
+
+/// Delete a stored file if it exists
+pub fn remove(name: &str) -> Result<(), String> {
+    platform::remove(name)
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_time() -> u64 {
+    platform::unix_time()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod platform {
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn path(name: &str) -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("ball_blitz").join(name))
+    }
+
+    pub fn read(name: &str) -> Option<String> {
+        std::fs::read_to_string(path(name)?).ok()
+    }
+
+    pub fn write(name: &str, contents: &str) -> Result<(), String> {
+        let path = path(name).ok_or("no data directory on this platform")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    pub fn remove(name: &str) -> Result<(), String> {
+        match path(name).map(std::fs::remove_file) {
+            Some(Err(err)) if err.kind() != std::io::ErrorKind::NotFound => Err(err.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn unix_time() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod platform {
+    fn local_storage() -> Result<web_sys::Storage, String> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .ok_or_else(|| "localStorage is not available".to_owned())
+    }
+
+    fn key(name: &str) -> String {
+        format!("ball_blitz/{}", name)
+    }
+
+    pub fn read(name: &str) -> Option<String> {
+        local_storage().ok()?.get_item(&key(name)).ok().flatten()
+    }
+
+    pub fn write(name: &str, contents: &str) -> Result<(), String> {
+        local_storage()?
+            .set_item(&key(name), contents)
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    pub fn remove(name: &str) -> Result<(), String> {
+        local_storage()?
+            .remove_item(&key(name))
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    pub fn unix_time() -> u64 {
+        (js_sys::Date::now() / 1000.0) as u64
+    }
+}
@@ -0,0 +1,164 @@
+//! Sliders and buttons for the menus
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+/// Width of a slider bar in pixels
+const SLIDER_WIDTH: f32 = 200.0;
+
+/// Horizontal bar with a value between 0 and 1, set by clicking or dragging on it
+#[derive(Component)]
+pub struct Slider {
+    pub value: f32,
+}
+
+/// Filled part of a slider bar
+#[derive(Component)]
+pub struct SliderFill;
+
+/// Label struct for a clickable button
+#[derive(Component)]
+pub struct MenuButton;
+
+/// Style of the text of the menus
+pub fn menu_text_style(asset_server: &AssetServer) -> TextStyle {
+    TextStyle {
+        font: asset_server.load("fonts/mononoki-Regular.ttf"),
+        font_size: 22.0,
+        color: Color::WHITE,
+    }
+}
+
+/// Spawn a row with a label and a slider, with a marker component on the slider
+pub fn spawn_slider(
+    parent: &mut ChildBuilder,
+    style: &TextStyle,
+    label: &str,
+    value: f32,
+    marker: impl Component,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(15.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(
+                TextBundle::from_section(label, style.clone()).with_style(Style {
+                    width: Val::Px(150.0),
+                    ..default()
+                }),
+            );
+            row.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(SLIDER_WIDTH),
+                        height: Val::Px(16.0),
+                        ..default()
+                    },
+                    background_color: Color::rgba(1.0, 1.0, 1.0, 0.2).into(),
+                    ..default()
+                },
+                Slider { value },
+                marker,
+            ))
+            .with_children(|bar| {
+                bar.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(value * 100.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        background_color: Color::rgb(0.9, 0.9, 0.9).into(),
+                        ..default()
+                    },
+                    SliderFill,
+                ));
+            });
+        });
+}
+
+/// Spawn a button with a text, with a marker component on the button
+pub fn spawn_button(
+    parent: &mut ChildBuilder,
+    style: &TextStyle,
+    text: &str,
+    marker: impl Component,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    padding: UiRect::axes(Val::Px(12.0), Val::Px(4.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(1.0, 1.0, 1.0, 0.2).into(),
+                ..default()
+            },
+            MenuButton,
+            marker,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(text, style.clone()));
+        });
+}
+
+/// Set the value of sliders that are pressed from the position of the cursor or touch
+pub fn drag_sliders(
+    mut sliders: Query<(&Interaction, &Node, &GlobalTransform, &mut Slider)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    touches: Res<Touches>,
+) {
+    let cursor = window
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+        .or_else(|| touches.first_pressed_position());
+    let Some(cursor) = cursor else {
+        return;
+    };
+    for (interaction, node, transform, mut slider) in sliders.iter_mut() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let left = transform.translation().x - node.size().x / 2.0;
+        let value = ((cursor.x - left) / node.size().x).clamp(0.0, 1.0);
+        if slider.value != value {
+            slider.value = value;
+        }
+    }
+}
+
+/// Resize the filled part of sliders to their value
+pub fn update_slider_fill(
+    sliders: Query<(&Slider, &Children), Changed<Slider>>,
+    mut fills: Query<&mut Style, With<SliderFill>>,
+) {
+    for (slider, children) in sliders.iter() {
+        for child in children.iter() {
+            if let Ok(mut style) = fills.get_mut(*child) {
+                style.width = Val::Percent(slider.value * 100.0);
+            }
+        }
+    }
+}
+
+/// Menu buttons that the cursor entered, left or pressed
+type ChangedButtons = (With<MenuButton>, Changed<Interaction>);
+
+/// Highlight buttons under the cursor
+pub fn highlight_buttons(mut buttons: Query<(&Interaction, &mut BackgroundColor), ChangedButtons>) {
+    for (interaction, mut color) in buttons.iter_mut() {
+        *color = match interaction {
+            Interaction::Pressed => Color::rgba(1.0, 1.0, 1.0, 0.5),
+            Interaction::Hovered => Color::rgba(1.0, 1.0, 1.0, 0.35),
+            Interaction::None => Color::rgba(1.0, 1.0, 1.0, 0.2),
+        }
+        .into();
+    }
+}
@@ -0,0 +1,297 @@
+use ball_blitz::actions::Rebinding;
+use ball_blitz::balls::{self, Ball, BallCatalog, BallDropped, BallSize, BallTemplates};
+use ball_blitz::highscores::HighScores;
+use ball_blitz::input::InputPlugin;
+use ball_blitz::points::GamePoints;
+use ball_blitz::settings::Settings;
+use ball_blitz::setup::{BoxScaleEvent, NewGameEvent};
+use ball_blitz::state::GameState;
+use ball_blitz::stats::{RoundOutcome, RoundStats};
+use ball_blitz::{BallBlitzConfig, BallBlitzPlugin};
+use bevy::ecs::system::RunSystemOnce;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::window::PrimaryWindow;
+use bevy_xpbd_3d::prelude::*;
+use std::time::Duration;
+
+/// Height of the floor of the default box
+const FLOOR: f32 = -6.0;
+
+/// Build the game without a window, still loading
+fn headless_app() -> App {
+    let catalog = BallCatalog::read("assets/balls.catalog.ron").unwrap();
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        TransformPlugin,
+        HierarchyPlugin,
+        AnimationPlugin,
+        PhysicsPlugins::default(),
+    ))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / 60.0,
+    )))
+    .init_asset::<Mesh>()
+    .init_asset::<StandardMaterial>()
+    .insert_resource(BallTemplates::without_meshes(catalog))
+    .add_plugins(BallBlitzPlugin {
+        config: BallBlitzConfig {
+            headless: true,
+            ..default()
+        },
+    });
+    app
+}
+
+/// Finish loading and start a round
+fn start_round(app: &mut App) {
+    // Loading finishes as soon as the templates exist
+    app.update();
+    app.update();
+    app.world.send_event(NewGameEvent);
+    app.update();
+    app.update();
+    assert_eq!(
+        *app.world.resource::<State<GameState>>(),
+        GameState::Playing
+    );
+}
+
+/// Build the game without a window and start a round
+fn game_app() -> App {
+    let mut app = headless_app();
+    start_round(&mut app);
+    app
+}
+
+/// Build the game with the input of the player and a window that is never shown, and start a
+/// round
+fn input_app() -> App {
+    let mut app = headless_app();
+    app.add_plugins(bevy::input::InputPlugin)
+        .init_asset::<Shader>()
+        .add_plugins(bevy::gizmos::GizmoPlugin)
+        .add_event::<CursorMoved>()
+        .init_resource::<Settings>()
+        .init_resource::<Rebinding>()
+        .init_resource::<HighScores>()
+        .add_plugins(InputPlugin);
+    app.world.spawn((Window::default(), PrimaryWindow));
+    start_round(&mut app);
+    app
+}
+
+/// Press and release a key over the next frame
+fn tap_key(app: &mut App, key_code: KeyCode) {
+    for state in [ButtonState::Pressed, ButtonState::Released] {
+        app.world.send_event(KeyboardInput {
+            scan_code: 0,
+            key_code: Some(key_code),
+            state,
+            window: Entity::PLACEHOLDER,
+        });
+        app.update();
+    }
+}
+
+fn radius(app: &App, size: u8) -> f32 {
+    app.world.resource::<BallTemplates>().radius(size)
+}
+
+/// Spawn a ball at a position without any meshes
+fn spawn_ball(app: &mut App, size: u8, translation: Vec3) -> Entity {
+    let mut ball = Ball::new(size, app.world.resource::<BallTemplates>());
+    ball.spatial.transform.translation = translation;
+    app.world.spawn(ball).id()
+}
+
+/// Return the sizes of every ball in the box, without the example ball
+fn ball_sizes(app: &mut App) -> Vec<u8> {
+    let mut sizes: Vec<u8> = app
+        .world
+        .query_filtered::<&BallSize, Without<balls::ExampleBall>>()
+        .iter(&app.world)
+        .map(|size| size.0)
+        .collect();
+    sizes.sort();
+    sizes
+}
+
+fn points(app: &App) -> i32 {
+    app.world.resource::<GamePoints>().0
+}
+
+/// Run frames until the balls merged, or fail after a second of game time
+fn run_until_sizes(app: &mut App, sizes: &[u8]) {
+    for _ in 0..60 {
+        app.update();
+        if ball_sizes(app) == sizes {
+            return;
+        }
+    }
+    panic!("Expected sizes {:?}, got {:?}", sizes, ball_sizes(app));
+}
+
+#[test]
+fn touching_balls_merge_into_next_size() {
+    let mut app = game_app();
+    let radius = radius(&app, 3);
+    spawn_ball(&mut app, 3, Vec3::new(-radius, FLOOR + radius, 0.0));
+    spawn_ball(&mut app, 3, Vec3::new(radius, FLOOR + radius, 0.0));
+    run_until_sizes(&mut app, &[4]);
+}
+
+#[test]
+fn merge_adds_size_squared_points() {
+    let mut app = game_app();
+    let radius = radius(&app, 3);
+    spawn_ball(&mut app, 3, Vec3::new(-radius, FLOOR + radius, 0.0));
+    spawn_ball(&mut app, 3, Vec3::new(radius, FLOOR + radius, 0.0));
+    run_until_sizes(&mut app, &[4]);
+    assert_eq!(points(&app), 3 * 3);
+}
+
+#[test]
+fn drop_adds_size_points() {
+    let mut app = game_app();
+    app.world.run_system_once(
+        |mut commands: Commands,
+         templates: Res<BallTemplates>,
+         mut dropped: EventWriter<BallDropped>| {
+            balls::drop_ball(
+                BallDropped {
+                    size: 2,
+                    position: Vec3::new(0.0, 5.0, 0.0),
+                    rotation: Quat::IDENTITY,
+                },
+                &templates,
+                &mut commands,
+                &mut dropped,
+            );
+        },
+    );
+    app.update();
+    assert_eq!(ball_sizes(&mut app), vec![2]);
+    assert_eq!(points(&app), 2);
+}
+
+#[test]
+fn ball_out_of_bounds_is_despawned_with_penalty() {
+    let mut app = game_app();
+    let ball = spawn_ball(&mut app, 3, Vec3::new(0.0, -60.0, 0.0));
+    app.update();
+    assert!(app.world.get_entity(ball).is_none());
+    assert_eq!(points(&app), -3 * 100);
+}
+
+#[test]
+fn creating_size_nine_grows_the_box() {
+    let mut app = game_app();
+    let mut scale_events = app.world.resource::<Events<BoxScaleEvent>>().get_reader();
+    let radius = radius(&app, 8);
+    // Stacked since two of them don't fit side by side in the box
+    spawn_ball(&mut app, 8, Vec3::new(0.0, FLOOR + radius, 0.0));
+    spawn_ball(&mut app, 8, Vec3::new(0.0, FLOOR + radius * 3.0, 0.0));
+    let mut sent = 0;
+    for _ in 0..60 {
+        app.update();
+        sent += scale_events
+            .read(app.world.resource::<Events<BoxScaleEvent>>())
+            .count();
+        if ball_sizes(&mut app) == [9] {
+            break;
+        }
+    }
+    assert_eq!(ball_sizes(&mut app), vec![9]);
+    assert_eq!(sent, 1);
+}
+
+#[test]
+fn ball_held_above_the_danger_line_ends_the_round() {
+    let mut app = game_app();
+    let ball = spawn_ball(&mut app, 1, Vec3::new(0.0, -FLOOR - 0.5, 0.0));
+    app.world.entity_mut(ball).insert(RigidBody::Static);
+    for _ in 0..60 * 5 {
+        app.update();
+    }
+    assert_eq!(
+        *app.world.resource::<State<GameState>>(),
+        GameState::GameOver
+    );
+    assert_eq!(
+        app.world.resource::<RoundStats>().outcome,
+        Some(RoundOutcome::Overflowed)
+    );
+}
+
+#[test]
+fn dropped_ball_passing_the_danger_line_does_not_end_the_round() {
+    let mut app = game_app();
+    spawn_ball(&mut app, 1, Vec3::new(0.0, -FLOOR, 0.0));
+    for _ in 0..60 * 5 {
+        app.update();
+    }
+    assert_eq!(
+        *app.world.resource::<State<GameState>>(),
+        GameState::Playing
+    );
+}
+
+#[test]
+fn creating_the_final_tier_wins_the_round() {
+    let mut app = game_app();
+    let final_tier = app.world.resource::<BallTemplates>().final_tier();
+    let size = final_tier - 1;
+    let radius = radius(&app, size);
+    spawn_ball(&mut app, size, Vec3::new(0.0, FLOOR + radius, 0.0));
+    spawn_ball(&mut app, size, Vec3::new(0.0, FLOOR + radius * 3.0, 0.0));
+    run_until_sizes(&mut app, &[final_tier]);
+    app.update();
+    assert_eq!(
+        *app.world.resource::<State<GameState>>(),
+        GameState::GameOver
+    );
+    assert_eq!(
+        app.world.resource::<RoundStats>().outcome,
+        Some(RoundOutcome::Won)
+    );
+}
+
+#[test]
+fn final_tier_balls_vanish_for_a_bonus() {
+    let mut app = game_app();
+    let templates = app.world.resource::<BallTemplates>();
+    let (final_tier, bonus) = (templates.final_tier(), templates.final_bonus());
+    let radius = radius(&app, final_tier);
+    spawn_ball(&mut app, final_tier, Vec3::new(0.0, FLOOR + radius, 0.0));
+    spawn_ball(
+        &mut app,
+        final_tier,
+        Vec3::new(0.0, FLOOR + radius * 3.0, 0.0),
+    );
+    run_until_sizes(&mut app, &[]);
+    assert_eq!(points(&app), bonus);
+}
+
+#[test]
+fn space_on_the_end_screen_starts_a_round_without_dropping() {
+    let mut app = input_app();
+    app.world
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::GameOver);
+    app.update();
+    tap_key(&mut app, KeyCode::Space);
+    for _ in 0..10 {
+        app.update();
+    }
+    assert_eq!(
+        *app.world.resource::<State<GameState>>(),
+        GameState::Playing
+    );
+    assert!(ball_sizes(&mut app).is_empty());
+    assert_eq!(app.world.resource::<RoundStats>().balls_dropped, 0);
+}